@@ -0,0 +1,31 @@
+//! ## Cleanup
+//! Functions run by the panic hook after the report is
+//! printed, so a crash does not leave sensitive data
+//! behind.
+
+use std::sync::{
+    Mutex,
+    PoisonError
+};
+
+/// Registered cleanup functions, in order of registration.
+static CLEANUPS: Mutex<Vec<fn()>> = Mutex::new(Vec::new());
+
+/// Registers `cleanup` to run when the application panics.
+pub fn on_panic(cleanup: fn()) {
+    CLEANUPS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(cleanup);
+}
+
+/// Runs every registered cleanup function.
+pub(crate) fn run() {
+    let cleanups = CLEANUPS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    for cleanup in cleanups {
+        cleanup();
+    }
+}
@@ -0,0 +1,50 @@
+//! ## Color
+//! Decides whether output is colored, shared with the
+//! logger so log lines and error reports agree.
+
+use std::env;
+use std::io::IsTerminal;
+
+/// Disables colors when set to anything but empty string.
+/// See <https://no-color.org>
+pub const NO_COLOR: &str = "NO_COLOR";
+
+/// Enables colors even if the output is not a terminal,
+/// when set to anything but `0`
+pub const CLICOLOR_FORCE: &str = "CLICOLOR_FORCE";
+
+/// Whether output is colored
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ColorChoice {
+    /// Colored if the output is a terminal which is not
+    /// `TERM=dumb`. `NO_COLOR` disables colors, otherwise
+    /// `CLICOLOR_FORCE` enables them anywhere
+    #[default]
+    Auto,
+    /// Always colored
+    Always,
+    /// Never colored
+    Never
+}
+
+impl ColorChoice {
+    /// Returns `true` if `stream` should be colored.
+    pub fn enabled(
+        self,
+        stream: &impl IsTerminal
+    ) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => {
+                if env::var_os(NO_COLOR).is_some_and(|value| !value.is_empty()) {
+                    return false;
+                }
+                if env::var_os(CLICOLOR_FORCE).is_some_and(|value| value != "0") {
+                    return true;
+                }
+                env::var_os("TERM").is_none_or(|term| term != "dumb") && stream.is_terminal()
+            }
+        }
+    }
+}
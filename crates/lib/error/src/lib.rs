@@ -5,6 +5,8 @@
 
 #![feature(thread_id_value)]
 
+use std::io;
+
 use color_eyre::Result;
 use color_eyre::config::{
     HookBuilder,
@@ -12,8 +14,17 @@ use color_eyre::config::{
 };
 use color_eyre::owo_colors::Style;
 
+mod cleanup;
+mod color;
 mod panic;
 
+pub use cleanup::on_panic;
+pub use color::{
+    CLICOLOR_FORCE,
+    ColorChoice,
+    NO_COLOR
+};
+
 /// Initializes error and panic reporting.
 ///
 /// Installs a color_eyre [`HookBuilder`] with
@@ -23,7 +34,11 @@ mod panic;
 ///
 /// Function should be first called in `main()`, so all
 /// errors and panics are reported with the desired
-/// formatting.
+/// formatting. Functions registered with [`on_panic`] run
+/// after the panic report is printed.
+///
+/// Reports are colored if stderr is a terminal, see
+/// [`ColorChoice::Auto`]; use [`install_with`] to choose.
 ///
 /// ### Possible Output of Error
 #[doc = r#"
@@ -132,22 +147,37 @@ mod panic;
 </pre>
 "#]
 #[inline]
-pub fn install() -> Result<()> {
-    HookBuilder::new()
-        .panic_message(panic::Panic)
-        .theme(
-            Theme::dark()
-                .error(Style::new().red())
-                .file(Style::new().purple().bold())
-                .hidden_frames(Style::new().bright_blue())
-                .crate_code(Style::new().green().bold())
-                .dependency_code(Style::new().yellow())
-                .help_info_note(Style::new().bright_green())
-                .active_line(Style::new().bright_red())
-                .spantrace_target(Style::new().green().bold())
-                .line_number(Style::new().purple().bold())
-        )
+pub fn install() -> Result<()> { install_with(ColorChoice::Auto) }
+
+/// Initializes error and panic reporting, like [`install`],
+/// with colors chosen by `color`.
+pub fn install_with(color: ColorChoice) -> Result<()> {
+    let color = color.enabled(&io::stderr());
+    let theme = if color {
+        Theme::dark()
+            .error(Style::new().red())
+            .file(Style::new().purple().bold())
+            .hidden_frames(Style::new().bright_blue())
+            .crate_code(Style::new().green().bold())
+            .dependency_code(Style::new().yellow())
+            .help_info_note(Style::new().bright_green())
+            .active_line(Style::new().bright_red())
+            .spantrace_target(Style::new().green().bold())
+            .line_number(Style::new().purple().bold())
+    } else {
+        Theme::new()
+    };
+    let (panic_hook, eyre_hook) = HookBuilder::new()
+        .panic_message(panic::Panic { color })
+        .theme(theme)
         .capture_span_trace_by_default(true)
         .display_location_section(true)
-        .install()
+        .try_into_hooks()?;
+
+    eyre_hook.install()?;
+    std::panic::set_hook(Box::new(move |info| {
+        eprintln!("{}", panic_hook.panic_report(info));
+        cleanup::run();
+    }));
+    Ok(())
 }
@@ -6,7 +6,10 @@
 
 use std::thread;
 
-use color_eyre::owo_colors::OwoColorize;
+use color_eyre::owo_colors::{
+    OwoColorize,
+    Style
+};
 use color_eyre::section::PanicMessage;
 
 /// A type representing an error report for a panic.
@@ -56,7 +59,20 @@ use color_eyre::section::PanicMessage;
                                 <font color=blue>⋮ 15 frames hidden ⋮</font>                              
 </pre>
 "#]
-pub(crate) struct Panic;
+pub(crate) struct Panic {
+    /// Whether the report is colored
+    pub(crate) color: bool
+}
+
+impl Panic {
+    /// Returns `style`, or a plain one without colors.
+    fn style(
+        &self,
+        style: Style
+    ) -> Style {
+        if self.color { style } else { Style::new() }
+    }
+}
 
 impl PanicMessage for Panic {
     fn display(
@@ -64,12 +80,15 @@ impl PanicMessage for Panic {
         info: &std::panic::PanicHookInfo<'_>,
         f: &mut std::fmt::Formatter<'_>
     ) -> std::fmt::Result {
+        let (value, location) = (
+            self.style(Style::new().blue()),
+            self.style(Style::new().purple())
+        );
         writeln!(
             f,
             "{}",
             "Unexpected error occured! The application panicked (crashed)."
-                .red()
-                .bold()
+                .style(self.style(Style::new().red().bold()))
         )?;
 
         let payload = info
@@ -80,21 +99,21 @@ impl PanicMessage for Panic {
             .unwrap_or("<???>");
 
         write!(f, "Message:   ")?;
-        writeln!(f, "{}", payload.blue())?;
+        writeln!(f, "{}", payload.style(value))?;
 
         if let Some(loc) = info.location() {
             writeln!(f, "Location: {{")?;
-            writeln!(f, "   file:   {}", loc.file().purple())?;
-            writeln!(f, "   line:   {}", loc.line().purple())?;
-            writeln!(f, "   column: {}", loc.column().purple())?;
+            writeln!(f, "   file:   {}", loc.file().style(location))?;
+            writeln!(f, "   line:   {}", loc.line().style(location))?;
+            writeln!(f, "   column: {}", loc.column().style(location))?;
             writeln!(f, "}}")?;
         } else {
             writeln!(
                 f,
                 "Location: {}:{}:{}",
-                "src/{unknown}.rs".purple(),
-                "??".purple(),
-                "??".purple()
+                "src/{unknown}.rs".style(location),
+                "??".style(location),
+                "??".style(location)
             )?;
         }
 
@@ -102,8 +121,14 @@ impl PanicMessage for Panic {
         writeln!(
             f,
             "{} (id: {})",
-            thread::current().name().unwrap_or("{unknown}").magenta(),
-            thread::current().id().as_u64().magenta()
+            thread::current()
+                .name()
+                .unwrap_or("{unknown}")
+                .style(self.style(Style::new().magenta())),
+            thread::current()
+                .id()
+                .as_u64()
+                .style(self.style(Style::new().magenta()))
         )?;
 
         Ok(())
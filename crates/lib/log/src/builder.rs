@@ -0,0 +1,298 @@
+//! ## Builder
+//! Module provides configuration of the logger: level and
+//! per-target filtering.
+
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::sync::OnceLock;
+use std::{
+    env,
+    io
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use niac_error::ColorChoice;
+use tracing_error::ErrorLayer;
+use tracing_subscriber::filter::{
+    Directive,
+    LevelFilter
+};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt as _;
+use tracing_subscriber::{
+    EnvFilter,
+    fmt
+};
+
+use super::file::{
+    LogFile,
+    Rotation
+};
+use super::format::{
+    Order,
+    Quote,
+    Tracer
+};
+use super::json::{
+    Json,
+    JsonFields
+};
+
+/// Environment variable with filter directives, in the
+/// `RUST_LOG` syntax, e.g. `info,bootstrap=trace`.
+pub const ENV: &str = "NIAC_LOG";
+
+/// Log file of the installed logger, for [`relocate`].
+static FILE: OnceLock<LogFile> = OnceLock::new();
+
+/// Layout of terminal output
+#[derive(Clone, Copy, Default)]
+pub enum Format {
+    /// Colored lines for humans, see
+    /// [`install`](crate::install)
+    #[default]
+    Text,
+    /// JSON object per line, with timestamp, level, target,
+    /// spans with their fields, location, message and
+    /// other fields of the event
+    Json
+}
+
+/// Configures the logger before installing it
+///
+/// Directives are applied in order: the [`level`],
+/// then every [`directive`], then the ones from
+/// [`NIAC_LOG`](ENV). Later directive for the same target
+/// wins, so the environment can always override the code.
+///
+/// [`level`]: Builder::level
+/// [`directive`]: Builder::directive
+pub struct Builder {
+    level:      LevelFilter,
+    directives: Vec<String>,
+    format:     Format,
+    color:      ColorChoice,
+    tracer:     Tracer,
+    stderr:     bool,
+    file:       Option<(PathBuf, Rotation)>
+}
+
+impl Default for Builder {
+    fn default() -> Self { Self::new() }
+}
+
+impl Builder {
+    /// Creates builder logging everything of `INFO` and
+    /// above
+    pub fn new() -> Self {
+        Self {
+            level:      LevelFilter::INFO,
+            directives: Vec::new(),
+            format:     Format::Text,
+            color:      ColorChoice::Auto,
+            tracer:     Tracer::default(),
+            stderr:     false,
+            file:       None
+        }
+    }
+
+    /// Sets the level of targets without a directive
+    #[must_use]
+    pub fn level(
+        mut self,
+        level: LevelFilter
+    ) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Adds a filter directive, e.g. `rops=warn`
+    #[must_use]
+    pub fn directive(
+        mut self,
+        directive: impl Into<String>
+    ) -> Self {
+        self.directives.push(directive.into());
+        self
+    }
+
+    /// Sets the layout of terminal output
+    #[must_use]
+    pub fn format(
+        mut self,
+        format: Format
+    ) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets whether terminal output is colored. Pass the
+    /// same choice to [`niac_error::install_with`], so
+    /// error reports agree with the log
+    #[must_use]
+    pub fn color(
+        mut self,
+        color: ColorChoice
+    ) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Sets when values of event fields are quoted in text
+    /// output
+    #[must_use]
+    pub fn quote(
+        mut self,
+        quote: Quote
+    ) -> Self {
+        self.tracer.quote = quote;
+        self
+    }
+
+    /// Sets the order of event fields in text output
+    #[must_use]
+    pub fn order(
+        mut self,
+        order: Order
+    ) -> Self {
+        self.tracer.order = order;
+        self
+    }
+
+    /// Writes terminal output to stderr instead of stdout,
+    /// e.g. when stdout carries data for another program
+    #[must_use]
+    pub fn stderr(
+        mut self,
+        stderr: bool
+    ) -> Self {
+        self.stderr = stderr;
+        self
+    }
+
+    /// Also writes every event to `path`, without colors
+    #[must_use]
+    pub fn file(
+        mut self,
+        path: impl Into<PathBuf>,
+        rotation: Rotation
+    ) -> Self {
+        self.file = Some((path.into(), rotation));
+        self
+    }
+
+    /// Builds the filter out of configured directives and
+    /// [`NIAC_LOG`](ENV).
+    fn filter(&self) -> Result<EnvFilter> {
+        let mut filter = EnvFilter::default().add_directive(self.level.into());
+        for directive in &self.directives {
+            filter = filter.add_directive(
+                directive
+                    .parse::<Directive>()
+                    .with_context(|| format!("Invalid log directive {directive}"))?
+            );
+        }
+
+        let Ok(directives) = env::var(ENV) else {
+            return Ok(filter);
+        };
+        for directive in directives
+            .split(',')
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+        {
+            filter = filter.add_directive(
+                directive
+                    .parse::<Directive>()
+                    .with_context(|| format!("Invalid log directive {directive} in {ENV}"))?
+            );
+        }
+        Ok(filter)
+    }
+
+    /// Returns stream of terminal output and whether it is
+    /// colored.
+    fn terminal(&self) -> (BoxMakeWriter, bool) {
+        if self.stderr {
+            (
+                BoxMakeWriter::new(io::stderr),
+                self.color.enabled(&io::stderr())
+            )
+        } else {
+            (
+                BoxMakeWriter::new(io::stdout),
+                self.color.enabled(&io::stdout())
+            )
+        }
+    }
+
+    /// Installs the logger as the global default
+    pub fn install(self) -> Result<()> {
+        let file = match &self.file {
+            Some((path, rotation)) => {
+                if FILE.get().is_some() {
+                    bail!("Logger is already installed");
+                }
+                let opened = LogFile::open(path, *rotation)?;
+                let file = FILE.get_or_init(|| opened);
+                Some(
+                    fmt::layer()
+                        .event_format(self.tracer)
+                        .with_ansi(false)
+                        .with_writer(move || file.writer())
+                )
+            },
+            None => None
+        };
+        let (writer, ansi) = self.terminal();
+        let (text, json) = match self.format {
+            Format::Text => (
+                Some(
+                    fmt::layer()
+                        .event_format(self.tracer)
+                        .with_ansi(ansi)
+                        .with_writer(writer)
+                ),
+                None
+            ),
+            Format::Json => (
+                None,
+                Some(
+                    fmt::layer()
+                        .fmt_fields(JsonFields)
+                        .event_format(Json)
+                        .with_writer(writer)
+                )
+            )
+        };
+        let subscriber = tracing_subscriber::registry()
+            .with(self.filter()?)
+            .with(text)
+            .with(json)
+            .with(file)
+            .with(ErrorLayer::default());
+
+        tracing::subscriber::set_global_default(subscriber).context("Failed to set logger")?;
+
+        tracing::info!("Logger initialized");
+        Ok(())
+    }
+}
+
+/// Moves the log file configured with [`Builder::file`] to
+/// `path`, keeping what was already written.
+///
+/// Useful when the final place of the log appears later,
+/// e.g. once the target root is mounted.
+pub fn relocate(path: &Path) -> Result<()> {
+    let Some(file) = FILE.get() else {
+        bail!("No log file is configured");
+    };
+    file.relocate(path)
+}
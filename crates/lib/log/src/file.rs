@@ -0,0 +1,217 @@
+//! ## File
+//! Module provides log file sink with rotation.
+//!
+//! Every event is written as a single line, so the file
+//! is rotated between events only.
+
+use std::fs::{
+    self,
+    File,
+    OpenOptions
+};
+use std::io::{
+    self,
+    Write
+};
+use std::os::unix::fs::OpenOptionsExt as _;
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::sync::{
+    Mutex,
+    MutexGuard,
+    PoisonError
+};
+
+use chrono::{
+    DateTime,
+    Local,
+    NaiveDate
+};
+use color_eyre::Result;
+use color_eyre::eyre::Context as _;
+
+/// Number of rotated files kept with [`Rotation::Size`].
+const KEEP: usize = 5;
+
+/// When the log file is moved aside for a new one
+#[derive(Clone, Copy)]
+pub enum Rotation {
+    /// Never, the file grows forever
+    Never,
+    /// On the first event of a new day. Old file gets the
+    /// date it was written at as a suffix, e.g.
+    /// `bootstrap.log.2015-10-20`
+    Daily,
+    /// When the file would grow over the given amount of
+    /// bytes. Old files are numbered from `.1`, newest
+    /// first, and only the last 5 are kept
+    Size(u64)
+}
+
+/// Opened log file.
+struct State {
+    path:    PathBuf,
+    file:    File,
+    /// Bytes written so far
+    size:    u64,
+    /// Day of the last write
+    written: NaiveDate
+}
+
+/// Opens `path` for appending, creating parent folders.
+///
+/// New file is readable only by the current user, since
+/// the log may mention paths and names of secrets.
+fn open(path: &Path) -> Result<State> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to open log file {}", path.display()))?;
+    let meta = file.metadata()?;
+    Ok(State {
+        path: path.to_path_buf(),
+        written: meta
+            .modified()
+            .map(|time| DateTime::<Local>::from(time).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive()),
+        size: meta.len(),
+        file
+    })
+}
+
+/// Returns `path` with `suffix` appended to its name.
+fn suffixed(
+    path: &Path,
+    suffix: impl std::fmt::Display
+) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{suffix}"));
+    PathBuf::from(name)
+}
+
+impl State {
+    /// Whether `len` more bytes go into a new file.
+    fn due(
+        &self,
+        rotation: Rotation,
+        len: u64
+    ) -> bool {
+        match rotation {
+            Rotation::Never => false,
+            Rotation::Daily => self.written != Local::now().date_naive(),
+            Rotation::Size(max) => self.size > 0 && self.size + len > max
+        }
+    }
+
+    /// Moves the file aside and opens a new one.
+    fn rotate(
+        &mut self,
+        rotation: Rotation
+    ) -> io::Result<()> {
+        match rotation {
+            Rotation::Never => return Ok(()),
+            Rotation::Daily => fs::rename(
+                &self.path,
+                suffixed(&self.path, self.written.format("%Y-%m-%d"))
+            )?,
+            Rotation::Size(_) => {
+                for index in (1..KEEP).rev() {
+                    let from = suffixed(&self.path, index);
+                    if from.exists() {
+                        fs::rename(from, suffixed(&self.path, index + 1))?;
+                    }
+                }
+                fs::rename(&self.path, suffixed(&self.path, 1))?;
+            }
+        }
+        *self = open(&self.path).map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+/// Log file shared by every event
+pub(crate) struct LogFile {
+    rotation: Rotation,
+    state:    Mutex<State>
+}
+
+impl LogFile {
+    /// Opens log file at `path`, appending to it.
+    pub(crate) fn open(
+        path: &Path,
+        rotation: Rotation
+    ) -> Result<Self> {
+        Ok(Self {
+            rotation,
+            state: Mutex::new(open(path)?)
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns writer of a single event, holding the file
+    /// until it is dropped.
+    pub(crate) fn writer(&self) -> LogWriter<'_> {
+        LogWriter {
+            rotation: self.rotation,
+            state:    self.lock()
+        }
+    }
+
+    /// Moves content of the log file to `path` and keeps
+    /// writing there.
+    ///
+    /// Content is copied rather than renamed, since `path`
+    /// is usually on another filesystem.
+    pub(crate) fn relocate(
+        &self,
+        path: &Path
+    ) -> Result<()> {
+        let mut state = self.lock();
+        if state.path == path {
+            return Ok(());
+        }
+        let mut new = open(path)?;
+        let mut old = File::open(&state.path)
+            .with_context(|| format!("Failed to read {}", state.path.display()))?;
+        new.size += io::copy(&mut old, &mut new.file)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        fs::remove_file(&state.path)
+            .with_context(|| format!("Failed to remove {}", state.path.display()))?;
+        *state = new;
+        Ok(())
+    }
+}
+
+/// Writer of a single event
+pub(crate) struct LogWriter<'a> {
+    rotation: Rotation,
+    state:    MutexGuard<'a, State>
+}
+
+impl Write for LogWriter<'_> {
+    fn write(
+        &mut self,
+        buf: &[u8]
+    ) -> io::Result<usize> {
+        if self.state.due(self.rotation, buf.len() as u64) {
+            self.state.rotate(self.rotation)?;
+        }
+        self.state.file.write_all(buf)?;
+        self.state.size += buf.len() as u64;
+        self.state.written = Local::now().date_naive();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.state.file.flush() }
+}
@@ -17,7 +17,60 @@ use tracing_subscriber::fmt::{
 use tracing_subscriber::registry::LookupSpan;
 
 use super::timer::Timer;
-use super::visitor::TracerVisitor;
+use super::visitor::{
+    FieldValue,
+    TracerVisitor
+};
+
+/// When field values are put in quotes
+///
+/// Applies to strings, errors and values recorded with `%`
+/// or `?`. Values already quoted by their `Debug` are left
+/// as they are.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Quote {
+    /// Only if the value is empty or has whitespace, `=`
+    /// or `"` in it
+    #[default]
+    Auto,
+    /// Always
+    Always,
+    /// Never
+    Never
+}
+
+impl Quote {
+    /// Returns `value` quoted according to `self`.
+    fn apply(
+        self,
+        value: &str
+    ) -> String {
+        let quoted = value.len() > 1 && value.starts_with('"') && value.ends_with('"');
+        let needed = match self {
+            Self::Auto =>
+                value.is_empty()
+                    || value
+                        .contains(|char: char| char.is_whitespace() || char == '=' || char == '"'),
+            Self::Always => true,
+            Self::Never => false
+        };
+        if needed && !quoted {
+            format!("{value:?}")
+        } else {
+            value.to_owned()
+        }
+    }
+}
+
+/// Order of fields after the message
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    /// As they are written in the macro
+    #[default]
+    Recorded,
+    /// By name
+    Sorted
+}
 
 /// Type for custom log formatting
 ///
@@ -28,19 +81,66 @@ use super::visitor::TracerVisitor;
  <font color="#AAAAAA">20.10.2015 18:39:37</font><font color="#284773"> ∥ </font><font color="#C23439"><b>ERROR</b></font><font color="#284773"> ∥ </font><font color="#AAAAAA">bootstrap_rs::main (src/main.rs:37): </font>Failed to read NIaC_SELF: environment variable not found
 </pre>
 "##]
-pub(crate) struct Tracer;
+/// Writers without ANSI support, like a log file, get the
+/// same layout with every escape sequence removed,
+/// including ones colored messages come with.
+///
+/// Fields other than the message follow it as dimmed
+/// `key=value` pairs.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Tracer {
+    pub(crate) quote: Quote,
+    pub(crate) order: Order
+}
 
-impl<S, F> FormatEvent<S, F> for Tracer
-where
-    S: Subscriber + for<'a> LookupSpan<'a>,
-    F: for<'a> FormatFields<'a> + 'static
-{
-    fn format_event(
+/// Removes ANSI escape sequences from `text`.
+pub(crate) fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(char) = chars.next() {
+        if char != '\x1b' {
+            plain.push(char);
+            continue;
+        }
+        // CSI sequence ends with a byte from `@` to `~`
+        if chars.next() == Some('[') {
+            for char in chars.by_ref() {
+                if ('@'..='~').contains(&char) {
+                    break;
+                }
+            }
+        }
+    }
+    plain
+}
+
+impl Tracer {
+    /// Returns `value` as written after `key=`.
+    fn value(
+        &self,
+        value: &FieldValue
+    ) -> String {
+        match value {
+            FieldValue::Str(value) | FieldValue::Error(value) | FieldValue::Debug(value) =>
+                self.quote.apply(value),
+            FieldValue::I64(value) => value.to_string(),
+            FieldValue::U64(value) => value.to_string(),
+            FieldValue::F64(value) => value.to_string(),
+            FieldValue::Bool(value) => value.to_string()
+        }
+    }
+
+    /// Writes colored `event`.
+    fn render<S, F>(
         &self,
         ctx: &FmtContext<'_, S, F>,
         mut writer: Writer<'_>,
         event: &Event<'_>
-    ) -> std::fmt::Result {
+    ) -> std::fmt::Result
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        F: for<'a> FormatFields<'a> + 'static
+    {
         let meta = event.metadata();
 
         Timer.format_time(&mut writer)?;
@@ -92,8 +192,42 @@ where
             .dimmed()
         )?;
 
-        write!(writer, "{}", visitor.msg.unwrap().truecolor(200, 200, 200))?;
+        let msg = visitor.msg.take().unwrap_or_default();
+        write!(writer, "{}", msg.truecolor(200, 200, 200))?;
+
+        if self.order == Order::Sorted {
+            visitor.fields.sort_by_key(|(name, _)| *name);
+        }
+        let mut sep = if msg.is_empty() { "" } else { " " };
+        for (name, value) in &visitor.fields {
+            write!(
+                writer,
+                "{sep}{}",
+                format!("{name}={}", self.value(value)).dimmed()
+            )?;
+            sep = " ";
+        }
 
         writeln!(writer)
     }
 }
+
+impl<S, F> FormatEvent<S, F> for Tracer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    F: for<'a> FormatFields<'a> + 'static
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, F>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>
+    ) -> std::fmt::Result {
+        if writer.has_ansi_escapes() {
+            return self.render(ctx, writer, event);
+        }
+        let mut line = String::new();
+        self.render(ctx, Writer::new(&mut line), event)?;
+        write!(writer, "{}", strip_ansi(&line))
+    }
+}
@@ -0,0 +1,110 @@
+//! ## JSON
+//! Module provides machine readable log format, one JSON
+//! object per line.
+
+use serde_json::{
+    Map,
+    Value,
+    json
+};
+use tracing::{
+    Event,
+    Subscriber
+};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{
+    FmtContext,
+    FormatEvent,
+    FormatFields,
+    FormattedFields
+};
+use tracing_subscriber::registry::LookupSpan;
+
+use super::visitor::JsonVisitor;
+
+/// Formatter of span fields as a JSON object
+///
+/// Fields are kept as JSON in span extensions, so [`Json`]
+/// can put them into the line as they are.
+pub(crate) struct JsonFields;
+
+/// Parses fields previously written by [`JsonFields`].
+fn parse(fields: &str) -> Map<String, Value> { serde_json::from_str(fields).unwrap_or_default() }
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R
+    ) -> std::fmt::Result {
+        let mut visitor = JsonVisitor::new(Map::new());
+        fields.record(&mut visitor);
+        write!(writer, "{}", Value::Object(visitor.fields))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &tracing::span::Record<'_>
+    ) -> std::fmt::Result {
+        let mut visitor = JsonVisitor::new(parse(&current.fields));
+        fields.record(&mut visitor);
+        current.fields = Value::Object(visitor.fields).to_string();
+        Ok(())
+    }
+}
+
+/// Type for JSON log formatting
+///
+/// #### Example output:
+/// ```json
+/// {"timestamp":"2015-10-20T18:39:36.120+02:00","level":"INFO","target":"bootstrap::stages","spans":[{"name":"stage","fields":{"name":"partition"}}],"file":"src/stages/mod.rs","line":220,"message":"Running: partition","fields":{}}
+/// ```
+pub(crate) struct Json;
+
+impl<S> FormatEvent<S, JsonFields> for Json
+where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>
+    ) -> std::fmt::Result {
+        let meta = event.metadata();
+
+        let mut visitor = JsonVisitor::new(Map::new());
+        event.record(&mut visitor);
+        let message = visitor.fields.remove("message").unwrap_or(Value::Null);
+
+        let spans = ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| {
+                let ext = span.extensions();
+                let fields = ext
+                    .get::<FormattedFields<JsonFields>>()
+                    .map(|fields| parse(&fields.fields))
+                    .unwrap_or_default();
+                json!({
+                    "name": span.metadata().name(),
+                    "fields": fields
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let line = json!({
+            "timestamp": chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            "level": meta.level().as_str(),
+            "target": meta.target(),
+            "spans": spans,
+            "file": meta.file(),
+            "line": meta.line(),
+            "message": message,
+            "fields": visitor.fields
+        });
+        writeln!(writer, "{line}")
+    }
+}
@@ -3,16 +3,31 @@
 //!
 //! Implement custom formatting
 
+mod builder;
+mod file;
 mod format;
+mod json;
 mod timer;
 mod visitor;
 
+pub use builder::{
+    Builder,
+    ENV,
+    Format,
+    relocate
+};
 use color_eyre::Result;
-use color_eyre::eyre::Context as _;
-use tracing_error::ErrorLayer;
-use tracing_subscriber::layer::SubscriberExt as _;
+pub use file::Rotation;
+pub use format::{
+    Order,
+    Quote
+};
+pub use niac_error::ColorChoice;
+pub use tracing_subscriber::filter::LevelFilter;
 
-/// Initializes logger with custom format
+/// Initializes logger with custom format and default
+/// [`Builder`] configuration: `INFO` and above, unless
+/// overridden by [`NIAC_LOG`](ENV).
 /// ### Example output:
 #[doc = r##"
 <pre>
@@ -21,14 +36,4 @@ use tracing_subscriber::layer::SubscriberExt as _;
 </pre>
 "##]
 #[inline]
-pub fn install() -> Result<()> {
-    let subscriber = tracing_subscriber::fmt()
-        .event_format(format::Tracer)
-        .finish()
-        .with(ErrorLayer::default());
-
-    tracing::subscriber::set_global_default(subscriber).context("Failed to set logger")?;
-
-    tracing::info!("Logger initialized");
-    Ok(())
-}
+pub fn install() -> Result<()> { Builder::new().install() }
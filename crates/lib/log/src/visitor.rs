@@ -2,28 +2,104 @@
 //! Module provides visitor type for logger for custom
 //! fields.
 
+use serde_json::{
+    Map,
+    Value
+};
 use tracing::field::Visit;
 
+use super::format::strip_ansi;
+
+/// Value of an event field, keeping its type
+pub(crate) enum FieldValue {
+    Str(String),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Bool(bool),
+    /// Display of an error
+    Error(String),
+    /// Debug or Display of anything else, e.g. `%host`
+    Debug(String)
+}
+
 /// Visitor type for logger
 pub(crate) struct TracerVisitor {
     /// Message of the event
-    pub(crate) msg: Option<String>
+    pub(crate) msg:    Option<String>,
+    /// Other fields, in the order they were recorded
+    pub(crate) fields: Vec<(&'static str, FieldValue)>
 }
 
 impl TracerVisitor {
     /// Creates new visitor with empty fields
-    pub(crate) fn new() -> Self { Self { msg: None } }
+    pub(crate) fn new() -> Self {
+        Self {
+            msg:    None,
+            fields: Vec::new()
+        }
+    }
+
+    fn insert(
+        &mut self,
+        field: &tracing::field::Field,
+        value: FieldValue
+    ) {
+        self.fields.push((field.name(), value));
+    }
 }
 
 impl Visit for TracerVisitor {
+    fn record_f64(
+        &mut self,
+        field: &tracing::field::Field,
+        value: f64
+    ) {
+        self.insert(field, FieldValue::F64(value));
+    }
+
+    fn record_i64(
+        &mut self,
+        field: &tracing::field::Field,
+        value: i64
+    ) {
+        self.insert(field, FieldValue::I64(value));
+    }
+
+    fn record_u64(
+        &mut self,
+        field: &tracing::field::Field,
+        value: u64
+    ) {
+        self.insert(field, FieldValue::U64(value));
+    }
+
+    fn record_bool(
+        &mut self,
+        field: &tracing::field::Field,
+        value: bool
+    ) {
+        self.insert(field, FieldValue::Bool(value));
+    }
+
     fn record_str(
         &mut self,
         field: &tracing::field::Field,
         value: &str
     ) {
         if field.name() == "message" {
-            self.msg = Some(value.to_owned())
-        };
+            self.msg = Some(value.to_owned());
+        } else {
+            self.insert(field, FieldValue::Str(value.to_owned()));
+        }
+    }
+
+    fn record_error(
+        &mut self,
+        field: &tracing::field::Field,
+        value: &(dyn std::error::Error + 'static)
+    ) {
+        self.insert(field, FieldValue::Error(value.to_string()));
     }
 
     fn record_debug(
@@ -32,7 +108,89 @@ impl Visit for TracerVisitor {
         value: &dyn std::fmt::Debug
     ) {
         if field.name() == "message" {
-            self.msg = Some(format!("{value:?}"))
-        };
+            self.msg = Some(format!("{value:?}"));
+        } else {
+            self.insert(field, FieldValue::Debug(format!("{value:?}")));
+        }
+    }
+}
+
+/// Visitor collecting every field as JSON
+///
+/// Strings are stripped of ANSI escapes, so colored
+/// messages stay readable for machines.
+pub(crate) struct JsonVisitor {
+    /// Field name to its value
+    pub(crate) fields: Map<String, Value>
+}
+
+impl JsonVisitor {
+    /// Creates visitor adding to `fields`
+    pub(crate) fn new(fields: Map<String, Value>) -> Self { Self { fields } }
+
+    fn insert(
+        &mut self,
+        field: &tracing::field::Field,
+        value: impl Into<Value>
+    ) {
+        self.fields.insert(field.name().to_owned(), value.into());
+    }
+}
+
+impl Visit for JsonVisitor {
+    fn record_f64(
+        &mut self,
+        field: &tracing::field::Field,
+        value: f64
+    ) {
+        self.insert(field, value);
+    }
+
+    fn record_i64(
+        &mut self,
+        field: &tracing::field::Field,
+        value: i64
+    ) {
+        self.insert(field, value);
+    }
+
+    fn record_u64(
+        &mut self,
+        field: &tracing::field::Field,
+        value: u64
+    ) {
+        self.insert(field, value);
+    }
+
+    fn record_bool(
+        &mut self,
+        field: &tracing::field::Field,
+        value: bool
+    ) {
+        self.insert(field, value);
+    }
+
+    fn record_str(
+        &mut self,
+        field: &tracing::field::Field,
+        value: &str
+    ) {
+        self.insert(field, strip_ansi(value));
+    }
+
+    fn record_error(
+        &mut self,
+        field: &tracing::field::Field,
+        value: &(dyn std::error::Error + 'static)
+    ) {
+        self.insert(field, strip_ansi(&value.to_string()));
+    }
+
+    fn record_debug(
+        &mut self,
+        field: &tracing::field::Field,
+        value: &dyn std::fmt::Debug
+    ) {
+        self.insert(field, strip_ansi(&format!("{value:?}")));
     }
 }
@@ -0,0 +1,164 @@
+//! ## Add
+//! Encrypts a new secret into the folder of a host or user.
+//!
+//! Content is read from stdin and stored the way SOPS
+//! stores binary files, so it is decrypted by the secrets
+//! stage like every other document.
+
+use std::fs;
+use std::io::{
+    self,
+    Read as _
+};
+use std::path::{
+    Component,
+    Path,
+    PathBuf
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use colored::Colorize as _;
+
+use crate::keys::{
+    self,
+    Owner
+};
+use crate::workspace::SecretWorkspace;
+use crate::{
+    cli,
+    input,
+    sops
+};
+
+/// Checks that `path` stays inside the folder of the owner
+/// and names a SOPS document.
+fn validate(path: &Path) -> Result<()> {
+    if path.as_os_str().is_empty()
+        || !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        bail!(
+            "Path {} must be relative to the folder of the owner",
+            path.display()
+        );
+    }
+    if path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(keys::is_master_key)
+    {
+        bail!("Master keys can not be added as secrets");
+    }
+    if path.extension().is_none_or(|ext| ext != sops::EXTENSION) {
+        bail!(
+            "Path {} must have the .{} extension",
+            path.display(),
+            sops::EXTENSION
+        );
+    }
+    Ok(())
+}
+
+/// Fails if document at `path` exists and `force` is not
+/// set.
+pub fn ensure_writable(
+    path: &Path,
+    force: bool
+) -> Result<()> {
+    if path.exists() && !force {
+        bail!(
+            "Secret {} already exists, use --force to overwrite it",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Returns sorted `recipients`, or ones of the master key
+/// of `owner` if none are given.
+pub fn recipients(
+    secrets: &Path,
+    owner: &Owner,
+    recipients: Vec<String>,
+    identity: Option<PathBuf>
+) -> Result<Vec<String>> {
+    let mut recipients = if recipients.is_empty() {
+        let workspace = SecretWorkspace::new("recipients")?;
+        keys::decrypt(
+            secrets,
+            workspace.path(),
+            owner.clone(),
+            &keys::unlockers(identity)
+        )?
+        .recipients()?
+    } else {
+        recipients
+    };
+    recipients.sort();
+    recipients.dedup();
+    Ok(recipients)
+}
+
+/// Encrypts `content` for `recipients` into `path`.
+///
+/// Existing document is overwritten only with `force`.
+pub fn write(
+    path: &Path,
+    content: String,
+    recipients: &[String],
+    force: bool
+) -> Result<()> {
+    ensure_writable(path, force)?;
+    let encrypted = sops::encrypt(sops::binary(content), recipients)
+        .with_context(|| format!("Failed to encrypt {}", path.display()))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(path, encrypted).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Encrypts stdin into the document given in `args`.
+pub fn run(
+    secrets: &Path,
+    args: cli::Add,
+    identity: Option<PathBuf>
+) -> Result<()> {
+    let owner = Owner::from(args.owner);
+    let span = tracing::info_span!("add", %owner);
+    let _guard = span.enter();
+
+    validate(&args.path)?;
+    let dir = secrets.join(owner.dir());
+    if !dir.is_dir() {
+        bail!("Folder {} not found!", dir.display());
+    }
+    let path = dir.join(&args.path);
+    // Checked before the master key is unlocked, too.
+    ensure_writable(&path, args.force)?;
+
+    if input::interactive() {
+        bail!("Content of the secret is read from stdin, which is a terminal");
+    }
+    let mut content = Vec::new();
+    io::stdin()
+        .read_to_end(&mut content)
+        .context("Failed to read stdin")?;
+    let content = String::from_utf8(content).context("Secret must be valid UTF-8")?;
+
+    let recipients = recipients(secrets, &owner, args.recipients, identity)?;
+    write(&path, content, &recipients, args.force)?;
+    tracing::info!(
+        "{} {} for {}",
+        "Added:".green().bold(),
+        path.strip_prefix(secrets)?.display(),
+        recipients.join(", ")
+    );
+    Ok(())
+}
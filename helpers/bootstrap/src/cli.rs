@@ -0,0 +1,305 @@
+//! ## CLI
+//! Command line arguments of the bootstrap script.
+//!
+//! Every value which can be asked interactively can also be
+//! passed here, so installation can be scripted. Without a
+//! subcommand, NixOS is installed.
+
+use std::path::PathBuf;
+
+use clap::{
+    ArgAction,
+    Parser,
+    Subcommand,
+    ValueEnum
+};
+use niac_log::{
+    ColorChoice,
+    Format,
+    LevelFilter
+};
+
+use crate::keys::{
+    Owner,
+    Protection
+};
+
+/// Bootstrap script for NIaC dotfiles
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Host to install. Must have a folder in `hosts/`
+    #[arg(long, value_name = "HOST")]
+    pub host: Option<String>,
+
+    /// User to set up. Must have a folder in `users/`.
+    /// Can be repeated
+    #[arg(long = "user", value_name = "USER")]
+    pub users: Vec<String>,
+
+    /// Path to the flake root. Searched upwards from `$PWD`
+    /// if not set
+    #[arg(long, value_name = "PATH", global = true)]
+    pub flake: Option<PathBuf>,
+
+    /// Plan file providing answers for the prompts
+    #[arg(long, value_name = "FILE")]
+    pub plan: Option<PathBuf>,
+
+    /// Assume "yes" for every confirmation
+    #[arg(long, short)]
+    pub yes: bool,
+
+    /// Continue a failed installation after the last
+    /// completed stage
+    #[arg(long, conflicts_with = "from_stage")]
+    pub resume: bool,
+
+    /// Start installation from the given stage, skipping
+    /// the previous ones
+    #[arg(long, value_name = "STAGE")]
+    pub from_stage: Option<String>,
+
+    /// Validate everything and print what would be done,
+    /// without touching disks or the target root
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// `age` identity file to unlock `masterKey.age` with,
+    /// instead of a FIDO2 token
+    #[arg(long, value_name = "FILE", global = true)]
+    pub identity: Option<PathBuf>,
+
+    /// State file used to resume installation
+    #[arg(long, value_name = "FILE")]
+    pub state: Option<PathBuf>,
+
+    /// Where disko mounts the target root
+    #[arg(long, value_name = "DIR", default_value = "/mnt")]
+    pub root: PathBuf,
+
+    /// Root to inspect hardware under, e.g. a fake tree
+    /// with `sys/`, `proc/` and `dev/` for testing
+    #[arg(long, value_name = "DIR", default_value = "/")]
+    pub sysroot: PathBuf,
+
+    /// Log more, `-vv` for everything. Overridden by
+    /// `NIAC_LOG`
+    #[arg(long, short, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Log less, `-qq` for errors only
+    #[arg(long, short, action = ArgAction::Count, global = true, conflicts_with = "verbose")]
+    pub quiet: u8,
+
+    /// Layout of log lines, `json` for parsing by machines
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value = "text",
+        global = true
+    )]
+    pub log_format: LogFormat,
+
+    /// Whether output is colored. `auto` honors `NO_COLOR`
+    /// and `CLICOLOR_FORCE`
+    #[arg(
+        long,
+        value_enum,
+        value_name = "WHEN",
+        default_value = "auto",
+        global = true
+    )]
+    pub color: Color
+}
+
+/// Layout of log lines.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json
+}
+
+/// When output is colored.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Color {
+    Auto,
+    Always,
+    Never
+}
+
+impl From<Color> for ColorChoice {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::Auto => Self::Auto,
+            Color::Always => Self::Always,
+            Color::Never => Self::Never
+        }
+    }
+}
+
+impl From<LogFormat> for Format {
+    fn from(format: LogFormat) -> Self {
+        match format {
+            LogFormat::Text => Self::Text,
+            LogFormat::Json => Self::Json
+        }
+    }
+}
+
+impl Args {
+    /// Returns log level chosen with `-v` and `-q`.
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.verbose, self.quiet) {
+            (0, 0) => LevelFilter::INFO,
+            (1, _) => LevelFilter::DEBUG,
+            (_, 0) => LevelFilter::TRACE,
+            (_, 1) => LevelFilter::WARN,
+            _ => LevelFilter::ERROR
+        }
+    }
+}
+
+/// Maintenance of the `secrets/` tree.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Re-encrypt secrets of a host or user for new
+    /// recipients, e.g. after rotating its master key
+    Rekey(Rekey),
+    /// Check every SOPS document and master key in
+    /// `secrets/`
+    VerifySecrets(Verify),
+    /// Manage SOPS documents of a host or user
+    #[command(subcommand)]
+    Secrets(Secrets),
+    /// Create configuration and master key of a new host
+    InitHost(Init),
+    /// Create configuration and master key of a new user
+    InitUser(Init)
+}
+
+/// Host or user owning secrets.
+#[derive(clap::Args)]
+#[group(required = true, multiple = false)]
+pub struct OwnerArgs {
+    /// Host in `secrets/hosts/`
+    #[arg(long, value_name = "HOST")]
+    pub host: Option<String>,
+
+    /// User in `secrets/users/`
+    #[arg(long, value_name = "USER")]
+    pub user: Option<String>
+}
+
+impl From<OwnerArgs> for Owner {
+    fn from(args: OwnerArgs) -> Self {
+        match (args.host, args.user) {
+            (Some(host), _) => Self::Host(host),
+            (None, Some(user)) => Self::User(user),
+            (None, None) => unreachable!("clap requires --host or --user")
+        }
+    }
+}
+
+/// Arguments of `rekey`.
+#[derive(clap::Args)]
+pub struct Rekey {
+    #[command(flatten)]
+    pub owner: OwnerArgs,
+
+    /// Previous encrypted master key, which secrets are
+    /// currently encrypted for. Defaults to the current one
+    #[arg(long, value_name = "FILE")]
+    pub old: Option<PathBuf>,
+
+    /// New `age` recipient. Can be repeated. Defaults to
+    /// the current master key
+    #[arg(long = "recipient", value_name = "AGE")]
+    pub recipients: Vec<String>
+}
+
+/// Arguments of `verify-secrets`.
+#[derive(clap::Args)]
+pub struct Verify {
+    /// Decrypt master keys to check MACs of the documents
+    #[arg(long)]
+    pub unlock: bool,
+
+    /// Write JSON report to the file, or to stdout with
+    /// `-`. Defaults to stdout with `--log-format json`
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<PathBuf>
+}
+
+/// Subcommands of `secrets`.
+#[derive(Subcommand)]
+pub enum Secrets {
+    /// Encrypt stdin into a new document of a host or user
+    Add(Add),
+    /// Generate SecureBoot keys of a host
+    Secureboot(SecureBootKeys)
+}
+
+/// Arguments of `secrets add`.
+#[derive(clap::Args)]
+pub struct Add {
+    #[command(flatten)]
+    pub owner: OwnerArgs,
+
+    /// Path of the document in the folder of the owner,
+    /// e.g. `secureBootKeys/db/key.age`
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// `age` recipient. Can be repeated. Defaults to the
+    /// master key of the owner
+    #[arg(long = "recipient", value_name = "AGE")]
+    pub recipients: Vec<String>,
+
+    /// Overwrite an existing document
+    #[arg(long)]
+    pub force: bool
+}
+
+/// Arguments of `secrets secureboot`.
+#[derive(clap::Args)]
+pub struct SecureBootKeys {
+    /// Host in `secrets/hosts/`
+    #[arg(long, value_name = "HOST")]
+    pub host: String,
+
+    /// `age` recipient. Can be repeated. Defaults to the
+    /// master key of the host
+    #[arg(long = "recipient", value_name = "AGE")]
+    pub recipients: Vec<String>,
+
+    /// Replace existing SecureBoot keys
+    #[arg(long)]
+    pub force: bool
+}
+
+/// Arguments of `init-host` and `init-user`.
+#[derive(clap::Args)]
+pub struct Init {
+    /// Name of the new host or user
+    #[arg(value_name = "NAME")]
+    pub name: String,
+
+    /// Existing host or user whose configuration is
+    /// copied. Without it, an empty folder is created
+    #[arg(long, value_name = "NAME")]
+    pub template: Option<String>,
+
+    /// How the master key is encrypted
+    #[arg(long, value_enum, default_value = "passphrase")]
+    pub protection: Protection,
+
+    /// `age` recipient of the token. Can be repeated.
+    /// Required with `--protection token`
+    #[arg(long = "recipient", value_name = "AGE")]
+    pub recipients: Vec<String>
+}
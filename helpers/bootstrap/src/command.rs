@@ -0,0 +1,143 @@
+//! ## Command
+//! Helpers for running external programs.
+
+use std::collections::VecDeque;
+use std::io::{
+    BufRead as _,
+    BufReader,
+    Read
+};
+use std::process::{
+    Command,
+    Stdio
+};
+use std::sync::mpsc;
+use std::thread;
+
+use color_eyre::eyre::{
+    Context as _,
+    bail,
+    eyre
+};
+use color_eyre::{
+    Result,
+    Section as _,
+    SectionExt as _
+};
+use colored::Colorize as _;
+
+/// Returns command line of `cmd` for logging.
+pub fn display(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Logs `cmd` instead of running it, for `--dry-run`.
+pub fn dry_run(cmd: &Command) {
+    let line = display(cmd);
+    tracing::info!("{} {}", "Would run:".yellow().bold(), line);
+}
+
+/// Runs `cmd` with inherited stdio, failing on non-zero
+/// exit code.
+pub fn run(cmd: &mut Command) -> Result<()> {
+    let line = display(cmd);
+    tracing::info!("{} {}", "Running:".blue().bold(), line);
+
+    let status = cmd
+        .status()
+        .with_context(|| format!("Failed to run {line}"))?;
+    if !status.success() {
+        bail!("Command {} failed with {status}", line.red());
+    }
+    Ok(())
+}
+
+/// Runs `cmd` and returns its stdout, failing on non-zero
+/// exit code.
+pub fn output(cmd: &mut Command) -> Result<String> {
+    let line = display(cmd);
+    tracing::debug!("{} {}", "Running:".blue().bold(), line);
+
+    let output = cmd
+        .output()
+        .with_context(|| format!("Failed to run {line}"))?;
+    if !output.status.success() {
+        return Err(
+            eyre!("Command {} failed with {}", line.red(), output.status).section(
+                String::from_utf8_lossy(&output.stderr)
+                    .trim_end()
+                    .to_owned()
+                    .header("Stderr:")
+            )
+        );
+    }
+    String::from_utf8(output.stdout).with_context(|| format!("Output of {line} is not UTF-8"))
+}
+
+/// Number of output lines kept for the error report of
+/// [`stream`].
+const TAIL: usize = 30;
+
+/// Sends lines of `pipe` to `sender` until it is closed.
+fn forward(
+    pipe: impl Read + Send + 'static,
+    sender: mpsc::Sender<String>
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Runs `cmd`, logging every line of its stdout and stderr
+/// in the current span, failing on non-zero exit code.
+///
+/// Error report has the last lines of the output in its
+/// `Output:` section.
+pub fn stream(cmd: &mut Command) -> Result<()> {
+    let line = display(cmd);
+    tracing::info!("{} {}", "Running:".blue().bold(), line);
+
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {line}"))?;
+
+    let (sender, receiver) = mpsc::channel();
+    let readers = [
+        child
+            .stdout
+            .take()
+            .map(|pipe| forward(pipe, sender.clone())),
+        child.stderr.take().map(|pipe| forward(pipe, sender))
+    ];
+
+    let mut tail = VecDeque::with_capacity(TAIL);
+    for output in receiver {
+        tracing::info!("{output}");
+        if tail.len() == TAIL {
+            tail.pop_front();
+        }
+        tail.push_back(output);
+    }
+    for reader in readers.into_iter().flatten() {
+        let _ = reader.join();
+    }
+
+    let status = child
+        .wait()
+        .with_context(|| format!("Failed to wait for {line}"))?;
+    if !status.success() {
+        return Err(eyre!("Command {} failed with {status}", line.red())
+            .section(Vec::from(tail).join("\n").header("Output:")));
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! ## Flake
+//! Discovery of the NIaC flake root.
+
+use std::env;
+use std::path::PathBuf;
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use colored::Colorize as _;
+use tracing::info;
+
+/// Returns root of the flake.
+///
+/// `preset` is used if given, then `NIaC_SELF` when built
+/// with Nix, otherwise flake is searched upwards from
+/// `$PWD`.
+pub fn find(preset: Option<PathBuf>) -> Result<PathBuf> {
+    let flake = if let Some(flake) = preset {
+        flake
+    } else if cfg!(feature = "nix-ready") {
+        PathBuf::from(env::var("NIaC_SELF").context("Failed to read NIaC_SELF")?)
+    } else {
+        info!("Searching {}...", "flake".blue());
+        env::current_dir()
+            .context("Failed to find flake by $PWD")
+            .and_then(|mut pwd| {
+                if pwd.join("flake.nix").exists() {
+                    Ok(pwd)
+                } else {
+                    info!(
+                        "Path \"{}\" does not contain a {}, searching up...",
+                        pwd.display().to_string().yellow(),
+                        "\"flake.nix\"".blue()
+                    );
+                    while !pwd.join("flake.nix").exists() {
+                        if !pwd.pop() {
+                            bail!("Failed to find flake root!");
+                        }
+                    }
+                    Ok(pwd)
+                }
+            })?
+    };
+    if !flake.join("flake.nix").exists() {
+        bail!("Path {} is not a flake root!", flake.display());
+    }
+    tracing::info!("{} {}", "Flake:".blue().bold(), flake.display());
+    Ok(flake)
+}
@@ -0,0 +1,609 @@
+//! ## Hardware
+//! Inspects the running machine and compares it with the
+//! host configuration.
+//!
+//! Everything is read relative to a root, `/` by default,
+//! so checks can be run against a fake tree with `sys/`,
+//! `proc/` and `dev/` in it.
+
+use std::collections::BTreeMap;
+use std::path::{
+    Component,
+    Path,
+    PathBuf
+};
+use std::{
+    fmt,
+    fs
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::Context as _;
+use serde::Deserialize;
+
+/// Stable names of block devices.
+pub const DISKS_BY_ID: &str = "dev/disk/by-id";
+
+/// Attributes of block devices.
+pub const BLOCK: &str = "sys/class/block";
+
+/// Unit of `size` of a block device.
+const SECTOR: u64 = 512;
+
+/// Present when the machine booted with UEFI.
+pub const EFI: &str = "sys/firmware/efi";
+
+/// CPU description with the vendor.
+pub const CPUINFO: &str = "proc/cpuinfo";
+
+/// Most symlinks followed by [`resolve`], as in Linux.
+const MAX_LINKS: usize = 40;
+
+/// GUID of the EFI global variables.
+const GLOBAL_VARIABLE: &str = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+
+/// Firmware interface the machine booted with.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Firmware {
+    Uefi,
+    Bios
+}
+
+impl fmt::Display for Firmware {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>
+    ) -> fmt::Result {
+        match self {
+            Self::Uefi => write!(f, "UEFI"),
+            Self::Bios => write!(f, "BIOS")
+        }
+    }
+}
+
+/// CPU vendor, named as in `hardware.cpu.<vendor>`.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Vendor {
+    Intel,
+    Amd
+}
+
+impl fmt::Display for Vendor {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>
+    ) -> fmt::Result {
+        match self {
+            Self::Intel => write!(f, "intel"),
+            Self::Amd => write!(f, "amd")
+        }
+    }
+}
+
+/// Disk found on the machine.
+pub struct Disk {
+    /// Name in `/dev/disk/by-id`
+    pub id:     String,
+    /// Kernel name, e.g. `nvme0n1`
+    pub block:  String,
+    /// Size in bytes
+    pub size:   Option<u64>,
+    pub model:  Option<String>,
+    pub serial: Option<String>
+}
+
+impl fmt::Display for Disk {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>
+    ) -> fmt::Result {
+        write!(f, "{} ({}", self.id, self.block)?;
+        if let Some(size) = self.size {
+            write!(f, ", {:.1} GB", size as f64 / 1e9)?;
+        }
+        for value in [&self.model, &self.serial].into_iter().flatten() {
+            write!(f, ", {value}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// What was found on the machine.
+pub struct Hardware {
+    /// Disks in `/dev/disk/by-id`
+    pub disks:       Vec<Disk>,
+    /// `None` if the vendor is unknown
+    pub cpu:         Option<Vendor>,
+    pub firmware:    Firmware,
+    /// `None` on BIOS or if the variable is not readable
+    pub setup_mode:  Option<bool>,
+    /// Whether SecureBoot is enforced, `None` as above
+    pub secure_boot: Option<bool>
+}
+
+/// Values of the host configuration checked against the
+/// machine.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Declared {
+    /// Disk name to `device`, as passed to `mkDisk`
+    #[serde(default)]
+    pub disks:      BTreeMap<String, String>,
+    /// Bootloader needs UEFI
+    pub uefi:       bool,
+    /// `lanzaboote` signs boot files for SecureBoot
+    pub lanzaboote: bool,
+    /// Vendor whose microcode updates are enabled
+    pub cpu:        Option<Vendor>
+}
+
+/// How serious a mismatch is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    /// Installation can continue
+    Warning,
+    /// Installation would fail or leave an unbootable
+    /// system
+    Error
+}
+
+/// Single mismatch between the machine and the
+/// configuration.
+pub struct Finding {
+    pub severity: Severity,
+    pub message:  String
+}
+
+impl Finding {
+    fn warning(message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message
+        }
+    }
+
+    fn error(message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            message
+        }
+    }
+}
+
+/// Returns `true` if `device` exists on the machine whose
+/// `/` is at `root`.
+pub fn present(
+    root: &Path,
+    device: &str
+) -> bool {
+    root.join(device.trim_start_matches('/'))
+        .symlink_metadata()
+        .is_ok()
+}
+
+/// Resolves `device` on the machine whose `/` is at `root`,
+/// e.g. `/dev/disk/by-id/<id>` to `/dev/nvme0n1`.
+///
+/// Symlinks are followed inside `root`, absolute ones
+/// too, so a fake tree never leads to devices of the live
+/// system. Returns `None` if the device does not exist.
+pub fn resolve(
+    root: &Path,
+    device: &str
+) -> Option<PathBuf> {
+    let mut device = normalize(Path::new(device));
+    for _ in 0..MAX_LINKS {
+        let path = root.join(device.strip_prefix("/").ok()?);
+        let Ok(target) = fs::read_link(&path) else {
+            return path.exists().then_some(device);
+        };
+        device = normalize(&device.parent()?.join(target));
+    }
+    None
+}
+
+/// Removes `.` and `..` from `path`, making it absolute,
+/// without looking at the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normal.pop();
+            },
+            Component::Normal(name) => normal.push(name),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    normal
+}
+
+/// Reads trimmed attribute of block device `block`.
+fn block_attr(
+    root: &Path,
+    block: &str,
+    attr: &str
+) -> Option<String> {
+    let value = fs::read_to_string(root.join(BLOCK).join(block).join(attr)).ok()?;
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_owned())
+}
+
+/// Lists disks of `/dev/disk/by-id`, without partitions.
+///
+/// Every disk has several names there, the one with the
+/// model and serial is preferred over `wwn-` and `eui.`
+/// ones.
+fn disks(root: &Path) -> Result<Vec<Disk>> {
+    let dir = root.join(DISKS_BY_ID);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if !name.contains("-part") {
+            names.push(name);
+        }
+    }
+    names.sort_by_key(|name| {
+        (
+            name.starts_with("wwn-") || name.contains("-eui."),
+            name.clone()
+        )
+    });
+
+    let mut disks = Vec::<Disk>::new();
+    for id in names {
+        let Some(block) = fs::read_link(dir.join(&id)).ok().and_then(|target| {
+            target
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        }) else {
+            continue;
+        };
+        if disks.iter().any(|disk| disk.block == block) {
+            continue;
+        }
+        disks.push(Disk {
+            size: block_attr(root, &block, "size")
+                .and_then(|sectors| sectors.parse::<u64>().ok())
+                .map(|sectors| sectors * SECTOR),
+            model: block_attr(root, &block, "device/model"),
+            serial: block_attr(root, &block, "device/serial"),
+            id,
+            block
+        });
+    }
+    disks.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(disks)
+}
+
+/// Reads CPU vendor from `/proc/cpuinfo`.
+fn cpu(root: &Path) -> Option<Vendor> {
+    let cpuinfo = fs::read_to_string(root.join(CPUINFO)).ok()?;
+    let vendor = cpuinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("vendor_id"))?
+        .trim_start_matches([' ', '\t', ':'])
+        .trim();
+    match vendor {
+        "GenuineIntel" => Some(Vendor::Intel),
+        "AuthenticAMD" => Some(Vendor::Amd),
+        _ => None
+    }
+}
+
+/// Reads boolean EFI global variable `name`.
+///
+/// `efivarfs` prefixes the value with 4 bytes of
+/// attributes.
+fn efi_flag(
+    root: &Path,
+    name: &str
+) -> Option<bool> {
+    let path = root
+        .join(EFI)
+        .join("efivars")
+        .join(format!("{name}-{GLOBAL_VARIABLE}"));
+    fs::read(path).ok()?.get(4).map(|value| *value == 1)
+}
+
+impl Hardware {
+    /// Inspects the machine whose `/` is at `root`.
+    pub fn detect(root: &Path) -> Result<Self> {
+        let firmware = if root.join(EFI).is_dir() {
+            Firmware::Uefi
+        } else {
+            Firmware::Bios
+        };
+        Ok(Self {
+            disks: disks(root)?,
+            cpu: cpu(root),
+            firmware,
+            setup_mode: efi_flag(root, "SetupMode"),
+            secure_boot: efi_flag(root, "SecureBoot")
+        })
+    }
+
+    /// Compares machine at `root` with the configuration.
+    pub fn check(
+        &self,
+        root: &Path,
+        declared: &Declared
+    ) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        for (name, device) in &declared.disks {
+            if !present(root, device) {
+                findings.push(Finding::error(format!(
+                    "Disk {name} ({device}) is not present, available: {}",
+                    if self.disks.is_empty() {
+                        "none".to_owned()
+                    } else {
+                        self.disks
+                            .iter()
+                            .map(|disk| disk.id.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    }
+                )));
+            }
+        }
+
+        if declared.uefi && self.firmware == Firmware::Bios {
+            findings.push(Finding::error(
+                "Bootloader of the host needs UEFI, but the machine booted with BIOS".to_owned()
+            ));
+        }
+        if !declared.uefi && self.firmware == Firmware::Uefi {
+            findings.push(Finding::warning(
+                "Machine booted with UEFI, but the host uses a BIOS bootloader".to_owned()
+            ));
+        }
+
+        if declared.lanzaboote && self.firmware == Firmware::Uefi {
+            match self.setup_mode {
+                Some(true) => {},
+                Some(false) => findings.push(Finding::warning(
+                    "SecureBoot is not in setup mode, keys can not be enrolled until it is reset \
+                     in the firmware"
+                        .to_owned()
+                )),
+                None => findings.push(Finding::warning(
+                    "SecureBoot setup mode is unknown, efivars are not readable".to_owned()
+                ))
+            }
+        }
+        if !declared.lanzaboote && self.secure_boot == Some(true) {
+            findings.push(Finding::warning(
+                "SecureBoot is enabled, but the host does not sign its boot files".to_owned()
+            ));
+        }
+
+        match (declared.cpu, self.cpu) {
+            (Some(declared), Some(found)) if declared != found => {
+                findings.push(Finding::warning(format!(
+                    "Host enables {declared} microcode, but the CPU is {found}"
+                )));
+            },
+            (None, Some(found)) => findings.push(Finding::warning(format!(
+                "CPU is {found}, but the host enables no microcode updates"
+            ))),
+            _ => {}
+        }
+
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Builds a machine with an NVMe and a SATA disk,
+    /// booted with UEFI in setup mode, with an AMD CPU.
+    fn machine() -> TempDir {
+        let root = TempDir::with_prefix("hardware").unwrap();
+        let path = root.path();
+
+        let by_id = path.join(DISKS_BY_ID);
+        fs::create_dir_all(&by_id).unwrap();
+        for (id, block) in [
+            ("nvme-Samsung_SSD_980_S64", "nvme0n1"),
+            ("nvme-Samsung_SSD_980_S64-part1", "nvme0n1p1"),
+            ("nvme-eui.002538b1", "nvme0n1"),
+            ("wwn-0x50014ee2", "sda"),
+            ("ata-WDC_WD10EZEX", "sda")
+        ] {
+            symlink(format!("../../{block}"), by_id.join(id)).unwrap();
+        }
+
+        for (block, sectors, model) in [
+            ("nvme0n1", "1953525168", "Samsung SSD 980"),
+            ("sda", "1953525168", "WDC WD10EZEX")
+        ] {
+            let dir = path.join(BLOCK).join(block);
+            fs::create_dir_all(dir.join("device")).unwrap();
+            fs::write(dir.join("size"), format!("{sectors}\n")).unwrap();
+            fs::write(dir.join("device/model"), format!("{model}  \n")).unwrap();
+        }
+
+        let efivars = path.join(EFI).join("efivars");
+        fs::create_dir_all(&efivars).unwrap();
+        fs::write(
+            efivars.join(format!("SetupMode-{GLOBAL_VARIABLE}")),
+            [7, 0, 0, 0, 1]
+        )
+        .unwrap();
+        fs::write(
+            efivars.join(format!("SecureBoot-{GLOBAL_VARIABLE}")),
+            [7, 0, 0, 0, 0]
+        )
+        .unwrap();
+
+        fs::create_dir_all(path.join("proc")).unwrap();
+        fs::write(
+            path.join(CPUINFO),
+            "processor\t: 0\nvendor_id\t: AuthenticAMD\ncpu family\t: 25\n"
+        )
+        .unwrap();
+
+        root
+    }
+
+    fn declared(disk: &str) -> Declared {
+        Declared {
+            disks:      BTreeMap::from([("main".to_owned(), disk.to_owned())]),
+            uefi:       true,
+            lanzaboote: true,
+            cpu:        Some(Vendor::Amd)
+        }
+    }
+
+    fn severities(findings: &[Finding]) -> Vec<Severity> {
+        findings.iter().map(|finding| finding.severity).collect()
+    }
+
+    #[test]
+    fn detects_machine() {
+        let root = machine();
+        let hardware = Hardware::detect(root.path()).unwrap();
+
+        let disks = hardware
+            .disks
+            .iter()
+            .map(|disk| (disk.id.as_str(), disk.block.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            disks,
+            [
+                ("ata-WDC_WD10EZEX", "sda"),
+                ("nvme-Samsung_SSD_980_S64", "nvme0n1")
+            ]
+        );
+        assert_eq!(hardware.disks[1].size, Some(1953525168 * SECTOR));
+        assert_eq!(hardware.disks[1].model.as_deref(), Some("Samsung SSD 980"));
+        assert_eq!(hardware.disks[1].serial, None);
+
+        assert!(matches!(hardware.firmware, Firmware::Uefi));
+        assert_eq!(hardware.setup_mode, Some(true));
+        assert_eq!(hardware.secure_boot, Some(false));
+        assert!(matches!(hardware.cpu, Some(Vendor::Amd)));
+    }
+
+    #[test]
+    fn detects_empty_machine() {
+        let root = TempDir::with_prefix("hardware").unwrap();
+        let hardware = Hardware::detect(root.path()).unwrap();
+
+        assert!(hardware.disks.is_empty());
+        assert!(matches!(hardware.firmware, Firmware::Bios));
+        assert_eq!(hardware.setup_mode, None);
+        assert!(hardware.cpu.is_none());
+    }
+
+    #[test]
+    fn resolves_disk_inside_root() {
+        let root = machine();
+        fs::write(root.path().join("dev/nvme0n1"), "").unwrap();
+        symlink(
+            "/dev/nvme0n1",
+            root.path().join(DISKS_BY_ID).join("nvme-absolute")
+        )
+        .unwrap();
+
+        for id in ["nvme-Samsung_SSD_980_S64", "nvme-absolute"] {
+            assert_eq!(
+                resolve(root.path(), &format!("/dev/disk/by-id/{id}")),
+                Some(PathBuf::from("/dev/nvme0n1"))
+            );
+        }
+        // Only the link exists, not the device it points to
+        assert_eq!(
+            resolve(root.path(), "/dev/disk/by-id/ata-WDC_WD10EZEX"),
+            None
+        );
+        assert_eq!(resolve(root.path(), "/dev/disk/by-id/nvme-Missing"), None);
+    }
+
+    #[test]
+    fn matching_machine_has_no_findings() {
+        let root = machine();
+        let hardware = Hardware::detect(root.path()).unwrap();
+
+        let findings = hardware.check(
+            root.path(),
+            &declared("/dev/disk/by-id/nvme-Samsung_SSD_980_S64")
+        );
+        assert!(findings.is_empty());
+    }
+
+    #[test]
+    fn missing_disk_is_error() {
+        let root = machine();
+        let hardware = Hardware::detect(root.path()).unwrap();
+
+        let findings = hardware.check(root.path(), &declared("/dev/disk/by-id/nvme-Missing"));
+        assert_eq!(severities(&findings), [Severity::Error]);
+        assert!(findings[0].message.contains("nvme-Missing"));
+        assert!(
+            findings[0]
+                .message
+                .contains("ata-WDC_WD10EZEX, nvme-Samsung_SSD_980_S64")
+        );
+    }
+
+    #[test]
+    fn mismatches_are_warnings() {
+        let root = machine();
+        let efivars = root.path().join(EFI).join("efivars");
+        fs::write(
+            efivars.join(format!("SetupMode-{GLOBAL_VARIABLE}")),
+            [7, 0, 0, 0, 0]
+        )
+        .unwrap();
+        let hardware = Hardware::detect(root.path()).unwrap();
+
+        let mut declared = declared("/dev/disk/by-id/ata-WDC_WD10EZEX");
+        declared.cpu = Some(Vendor::Intel);
+        let findings = hardware.check(root.path(), &declared);
+        assert_eq!(
+            severities(&findings),
+            [Severity::Warning, Severity::Warning]
+        );
+        assert!(findings[0].message.contains("setup mode"));
+        assert!(findings[1].message.contains("intel microcode"));
+    }
+
+    #[test]
+    fn uefi_host_on_bios_is_error() {
+        let root = machine();
+        fs::remove_dir_all(root.path().join(EFI)).unwrap();
+        let hardware = Hardware::detect(root.path()).unwrap();
+
+        let findings = hardware.check(
+            root.path(),
+            &declared("/dev/disk/by-id/nvme-Samsung_SSD_980_S64")
+        );
+        assert_eq!(severities(&findings), [Severity::Error]);
+        assert!(findings[0].message.contains("BIOS"));
+    }
+
+    #[test]
+    fn present_accepts_absolute_paths() {
+        let root = machine();
+        assert!(present(
+            root.path(),
+            "/dev/disk/by-id/nvme-Samsung_SSD_980_S64"
+        ));
+        assert!(!present(root.path(), "/dev/disk/by-id/nvme-Missing"));
+    }
+}
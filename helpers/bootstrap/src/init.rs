@@ -0,0 +1,158 @@
+//! ## Init
+//! Scaffolding of new hosts and users.
+//!
+//! Configuration is copied from a template host or user,
+//! and a new master key is generated into the secrets
+//! folder. Recipient of the key is registered in
+//! `.sops.yaml`, so new documents can be encrypted for it.
+//!
+//! New `.sops.yaml` is checked before anything is written,
+//! so a failed registration leaves no half set up owner.
+
+use std::fs;
+use std::path::{
+    Component,
+    Path
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use colored::Colorize as _;
+
+use crate::keys::{
+    self,
+    Owner
+};
+use crate::{
+    cli,
+    rules
+};
+
+/// Folder with configurations of hosts, relative to the
+/// flake root.
+pub const HOSTS_DIR: &str = "configurations/hosts";
+
+/// Folder with configurations of users, relative to the
+/// flake root.
+pub const USERS_DIR: &str = "configurations/users";
+
+/// Checks that `name` can be used as a folder name.
+fn validate(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        bail!("Invalid name {name}");
+    }
+    Ok(())
+}
+
+/// Recursively copies `source` into `target`.
+fn copy_dir(
+    source: &Path,
+    target: &Path
+) -> Result<()> {
+    fs::create_dir_all(target).with_context(|| format!("Failed to create {}", target.display()))?;
+    for entry in
+        fs::read_dir(source).with_context(|| format!("Failed to read {}", source.display()))?
+    {
+        let entry = entry?;
+        let (from, to) = (entry.path(), target.join(entry.file_name()));
+        if entry.file_type()?.is_dir() {
+            copy_dir(&from, &to)?;
+        } else {
+            fs::copy(&from, &to).with_context(|| {
+                format!("Failed to copy {} to {}", from.display(), to.display())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates configuration and master key of `owner`, with
+/// configurations in `configs`.
+fn init(
+    flake: &Path,
+    owner: &Owner,
+    configs: &str,
+    args: cli::Init
+) -> Result<()> {
+    let span = tracing::info_span!("init", %owner);
+    let _guard = span.enter();
+
+    let config = flake.join(configs).join(&args.name);
+    if config.exists() {
+        bail!("Configuration {} already exists", config.display());
+    }
+    let template = match &args.template {
+        Some(template) => {
+            validate(template)?;
+            let source = flake.join(configs).join(template);
+            if !source.is_dir() {
+                bail!("Template {} not found!", source.display());
+            }
+            Some(source)
+        },
+        None => None
+    };
+
+    let secrets = flake.join("secrets");
+    let key = keys::generate(&secrets, owner)?;
+    let registration = rules::register(flake, owner, &key.recipient)?;
+    let recipient = key.recipient.clone();
+
+    key.save(args.protection, &args.recipients)?;
+    tracing::info!(
+        "{} master key of {owner} in {}",
+        "Generated:".green().bold(),
+        secrets.join(owner.dir()).display()
+    );
+
+    match template {
+        Some(source) => {
+            copy_dir(&source, &config)?;
+            tracing::info!(
+                "{} {} from {}, review it before installing",
+                "Created:".green().bold(),
+                config.display(),
+                source.display()
+            );
+        },
+        None => {
+            fs::create_dir_all(&config)
+                .with_context(|| format!("Failed to create {}", config.display()))?;
+            tracing::warn!(
+                "No --template given, configuration {} is empty and must be filled manually",
+                config.display()
+            );
+        }
+    }
+
+    registration.save()?;
+    tracing::info!("{} {recipient}", "Recipient:".blue().bold());
+    Ok(())
+}
+
+/// Creates configuration and master key of the host given
+/// in `args`.
+pub fn host(
+    flake: &Path,
+    args: cli::Init
+) -> Result<()> {
+    validate(&args.name)?;
+    init(flake, &Owner::Host(args.name.clone()), HOSTS_DIR, args)
+}
+
+/// Creates configuration and master key of the user given
+/// in `args`.
+pub fn user(
+    flake: &Path,
+    args: cli::Init
+) -> Result<()> {
+    validate(&args.name)?;
+    init(flake, &Owner::User(args.name.clone()), USERS_DIR, args)
+}
@@ -0,0 +1,183 @@
+//! ## Input
+//! Resolves host and users to install.
+//!
+//! Values from the [`Plan`](crate::plan::Plan) are
+//! validated first. Prompts are shown only when a value is
+//! missing and stdin is a terminal, so scripted runs fail
+//! instead of hanging.
+
+use std::fs::File;
+use std::io::{
+    self,
+    IsTerminal as _
+};
+use std::path::Path;
+use std::thread::sleep;
+use std::time::Duration;
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use colored::Colorize as _;
+
+/// Returns `true` if prompts can be shown.
+pub fn interactive() -> bool { io::stdin().is_terminal() }
+
+/// Returns `true` if secrets can be asked.
+///
+/// Unlike [`interactive`], stdin may be redirected, e.g. to
+/// carry content of a new secret. Prompts then read from
+/// the controlling terminal.
+pub fn terminal() -> bool {
+    interactive() || (io::stderr().is_terminal() && File::open("/dev/tty").is_ok())
+}
+
+/// Checks that configuration of `host` exists.
+fn host_exists(
+    flake: &Path,
+    host: &str
+) -> bool {
+    tracing::info!("Checking if host configuration exists...");
+    let dir = flake.join("hosts").join(host);
+    if dir.exists() {
+        true
+    } else {
+        tracing::error!(
+            "Folder {} {}",
+            dir.to_string_lossy().underline(),
+            "not found!".red().bold()
+        );
+        false
+    }
+}
+
+/// Returns users which have no configuration.
+fn invalid_users(
+    flake: &Path,
+    users: &[String]
+) -> Vec<String> {
+    tracing::info!("Checking if all users configurations exist...");
+
+    let mut invalid_users = Vec::<String>::new();
+    for user in users {
+        let dir = flake.join("users").join(user);
+        if !dir.exists() {
+            tracing::error!(
+                "Folder {} {}",
+                dir.display().to_string().underline(),
+                "not found!".red()
+            );
+            invalid_users.push(user.into());
+        }
+    }
+    invalid_users
+}
+
+/// Resolves host, prompting for it if `preset` is empty.
+pub fn host(
+    flake: &Path,
+    preset: Option<String>
+) -> Result<String> {
+    if let Some(host) = preset {
+        if !host_exists(flake, &host) {
+            bail!("Host {} is invalid!", host.red().underline());
+        }
+        return Ok(host);
+    }
+    if !interactive() {
+        bail!("Host is not set and stdin is not a terminal, pass it with --host");
+    }
+
+    loop {
+        let input = dialoguer::Input::<'_, String>::new()
+            .with_prompt("Host".blue().bold().underline().to_string())
+            .interact_text()
+            .inspect_err(|_| sleep(Duration::from_millis(1)))
+            .context("Failed to recieve input")?;
+
+        if input.is_empty() {
+            tracing::error!("No hostname entered");
+            continue;
+        }
+        if host_exists(flake, &input) {
+            break Ok(input);
+        } else {
+            println!(
+                "Hostname {} is {} Try again.",
+                input.red().underline(),
+                "invalid!".red().bold()
+            );
+        }
+    }
+}
+
+/// Resolves users, prompting for them if `preset` is empty.
+pub fn users(
+    flake: &Path,
+    preset: Vec<String>
+) -> Result<Vec<String>> {
+    if !preset.is_empty() {
+        let invalid_users = invalid_users(flake, &preset);
+        if !invalid_users.is_empty() {
+            bail!("Users {} are invalid!", invalid_users.join(", ").red());
+        }
+        return Ok(preset);
+    }
+    if !interactive() {
+        bail!("Users are not set and stdin is not a terminal, pass them with --user");
+    }
+
+    loop {
+        let input = dialoguer::Input::<'_, String>::new()
+            .with_prompt("Users".blue().bold().underline().to_string())
+            .interact_text()
+            .context("Failed to recieve input")?
+            .split_whitespace()
+            .map(|s| s.into())
+            .collect::<Vec<String>>();
+
+        if input.is_empty() {
+            tracing::error!("No usernames entered");
+            continue;
+        }
+
+        let invalid_users = invalid_users(flake, &input);
+        if invalid_users.is_empty() {
+            break Ok(input);
+        } else {
+            println!(
+                "Users {} are {} Try again.",
+                invalid_users
+                    .iter()
+                    .map(|user| user.red().underline().to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                "invalid".red().bold()
+            )
+        }
+    }
+}
+
+/// Asks user to confirm an action.
+///
+/// Always succeeds with `yes`. Fails when confirmation is
+/// needed but stdin is not a terminal.
+pub fn confirm(
+    prompt: &str,
+    yes: bool
+) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if !interactive() {
+        bail!("Confirmation required but stdin is not a terminal, pass --yes");
+    }
+
+    dialoguer::Confirm::new()
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+        .context("Failed to recieve input")
+}
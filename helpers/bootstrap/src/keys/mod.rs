@@ -0,0 +1,291 @@
+//! ## Keys
+//! Generation and decryption of master keys.
+//!
+//! Every host and user has a master key in
+//! `secrets/{hosts,users}/<name>/`. It is an `age`
+//! identity, encrypted either to a hardware token
+//! (`masterKey.age`) or with a passphrase as an OpenPGP
+//! message (`masterKey.asc`). Each way is a
+//! [`KeyUnlocker`].
+
+mod passphrase;
+mod plugin;
+
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::{
+    fmt,
+    fs
+};
+
+use age::secrecy::ExposeSecret as _;
+use age::x25519;
+use chrono::SecondsFormat;
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use rops::integration::{
+    AgeIntegration,
+    Integration as _
+};
+
+use crate::secret;
+
+/// Owner of a master key.
+#[derive(Clone)]
+pub enum Owner {
+    Host(String),
+    User(String)
+}
+
+impl Owner {
+    /// Returns owner of the file at `path`, relative to
+    /// `secrets/`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let mut components = path.iter().map(|component| component.to_str());
+        let (kind, name) = (components.next()??, components.next()??);
+        components.next()?;
+        match (kind, name) {
+            ("hosts", name) => Some(Self::Host(name.to_owned())),
+            ("users", name) => Some(Self::User(name.to_owned())),
+            _ => None
+        }
+    }
+
+    /// Directory of the owner, relative to `secrets/`.
+    pub fn dir(&self) -> PathBuf {
+        match self {
+            Self::Host(name) => Path::new("hosts").join(name),
+            Self::User(name) => Path::new("users").join(name)
+        }
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>
+    ) -> fmt::Result {
+        match self {
+            Self::Host(name) => write!(f, "host {name}"),
+            Self::User(name) => write!(f, "user {name}")
+        }
+    }
+}
+
+/// Decrypted master key.
+#[derive(Clone)]
+pub struct MasterKey {
+    /// Owner of the key
+    pub owner: Owner,
+    /// Path to the decrypted key
+    pub path:  PathBuf
+}
+
+impl MasterKey {
+    /// Reads `age` identities from the decrypted key,
+    /// skipping comments.
+    pub fn identities(&self) -> Result<Vec<String>> {
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+
+        let mut identities = Vec::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            AgeIntegration::parse_private_key(line)
+                .with_context(|| format!("Master key of {} is not an age identity", self.owner))?;
+            identities.push(line.to_owned());
+        }
+        if identities.is_empty() {
+            bail!("Master key of {} has no age identities", self.owner);
+        }
+        Ok(identities)
+    }
+
+    /// Returns `age` recipients of the key.
+    pub fn recipients(&self) -> Result<Vec<String>> {
+        self.identities()?
+            .iter()
+            .map(|identity| {
+                AgeIntegration::parse_private_key(identity)
+                    .map(|identity| identity.to_public().to_string())
+                    .with_context(|| format!("Master key of {} is not an age identity", self.owner))
+            })
+            .collect()
+    }
+}
+
+/// Way to decrypt a master key.
+pub trait KeyUnlocker {
+    /// Name of the encrypted master key file in the folder
+    /// of the owner.
+    fn file(&self) -> &'static str;
+
+    /// Decrypts `encrypted` master key of `owner`.
+    fn unlock(
+        &self,
+        owner: &Owner,
+        encrypted: &[u8]
+    ) -> Result<Vec<u8>>;
+}
+
+/// Returns every unlocker, preferring hardware tokens.
+///
+/// `identity` replaces the default FIDO2 plugin.
+pub fn unlockers(identity: Option<PathBuf>) -> Vec<Box<dyn KeyUnlocker>> {
+    vec![
+        Box::new(plugin::Plugin { identity }),
+        Box::new(passphrase::Passphrase),
+    ]
+}
+
+/// Names of encrypted master key files, one per unlocker.
+pub const MASTER_KEYS: [&str; 2] = [plugin::FILE, passphrase::FILE];
+
+/// Returns `true` if `name` is an encrypted master key.
+pub fn is_master_key(name: &str) -> bool { MASTER_KEYS.contains(&name) }
+
+/// How a new master key is encrypted.
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Protection {
+    /// OpenPGP message encrypted with a passphrase
+    Passphrase,
+    /// `age` file encrypted to a hardware token
+    Token
+}
+
+/// New master key, not written yet.
+pub struct NewKey {
+    owner:         Owner,
+    dir:           PathBuf,
+    identity:      x25519::Identity,
+    /// `age` recipient of the key
+    pub recipient: String
+}
+
+/// Generates a new master key of `owner`, to be written
+/// into the folder of the owner in `secrets` with
+/// [`NewKey::save`].
+///
+/// Fails if the owner has a master key already.
+pub fn generate(
+    secrets: &Path,
+    owner: &Owner
+) -> Result<NewKey> {
+    let dir = secrets.join(owner.dir());
+    if let Some(existing) = MASTER_KEYS
+        .iter()
+        .map(|file| dir.join(file))
+        .find(|path| path.exists())
+    {
+        bail!("Master key {} already exists", existing.display());
+    }
+
+    let identity = x25519::Identity::generate();
+    Ok(NewKey {
+        owner: owner.clone(),
+        dir,
+        recipient: identity.to_public().to_string(),
+        identity
+    })
+}
+
+impl NewKey {
+    /// Encrypts the key and writes it into the folder of
+    /// the owner.
+    ///
+    /// `recipients` are the ones of the token, used only
+    /// with [`Protection::Token`].
+    pub fn save(
+        self,
+        protection: Protection,
+        recipients: &[String]
+    ) -> Result<()> {
+        let plain = format!(
+            "# created: {}\n# public key: {}\n{}\n",
+            chrono::Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            self.recipient,
+            self.identity.to_string().expose_secret()
+        );
+        let (file, encrypted) = match protection {
+            Protection::Passphrase => (
+                passphrase::FILE,
+                passphrase::lock(&self.owner, plain.as_bytes())?
+            ),
+            Protection::Token => (plugin::FILE, plugin::lock(plain.as_bytes(), recipients)?)
+        };
+
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = self.dir.join(file);
+        fs::write(&path, encrypted).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Decrypts master key of `owner` into `output`.
+///
+/// First unlocker whose file exists is used. Decrypted key
+/// keeps the layout of `secrets/` and is readable only by
+/// the current user.
+pub fn decrypt(
+    secrets: &Path,
+    output: &Path,
+    owner: Owner,
+    unlockers: &[Box<dyn KeyUnlocker>]
+) -> Result<MasterKey> {
+    let dir = secrets.join(owner.dir());
+    let Some(source) = unlockers
+        .iter()
+        .map(|unlocker| dir.join(unlocker.file()))
+        .find(|source| source.exists())
+    else {
+        bail!(
+            "No master key of {owner} found in {}, expected one of: {}",
+            dir.display(),
+            unlockers
+                .iter()
+                .map(|unlocker| unlocker.file())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    };
+    decrypt_file(&source, &output.join(owner.dir()), owner, unlockers)
+}
+
+/// Decrypts master key of `owner` stored in `source` into
+/// `output`.
+///
+/// Unlocker is chosen by the extension of `source`, so
+/// renamed keys, e.g. `masterKey.asc.old`, are not
+/// supported.
+pub fn decrypt_file(
+    source: &Path,
+    output: &Path,
+    owner: Owner,
+    unlockers: &[Box<dyn KeyUnlocker>]
+) -> Result<MasterKey> {
+    let span = tracing::info_span!("decrypt", %owner);
+    let _guard = span.enter();
+
+    let Some(unlocker) = unlockers
+        .iter()
+        .find(|unlocker| Path::new(unlocker.file()).extension() == source.extension())
+    else {
+        bail!("Unknown master key format of {}", source.display());
+    };
+
+    let encrypted =
+        fs::read(source).with_context(|| format!("Failed to read {}", source.display()))?;
+    let plain = unlocker.unlock(&owner, &encrypted)?;
+
+    let path = output.join("masterKey");
+    secret::write(&path, &plain)?;
+
+    Ok(MasterKey { owner, path })
+}
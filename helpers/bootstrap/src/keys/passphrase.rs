@@ -0,0 +1,199 @@
+//! ## Passphrase unlocker
+//! Master key encrypted with a passphrase as an OpenPGP
+//! message, stored in `masterKey.asc`.
+
+use std::io::{
+    Read as _,
+    Write as _
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail,
+    eyre
+};
+use colored::Colorize as _;
+use openpgp::armor;
+use openpgp::crypto::{
+    Password,
+    SessionKey
+};
+use openpgp::packet::{
+    PKESK,
+    SKESK
+};
+use openpgp::parse::Parse as _;
+use openpgp::parse::stream::{
+    DecryptionHelper,
+    DecryptorBuilder,
+    MessageStructure,
+    VerificationHelper
+};
+use openpgp::policy::StandardPolicy;
+use openpgp::serialize::stream::{
+    Armorer,
+    Encryptor,
+    LiteralWriter,
+    Message
+};
+use openpgp::types::SymmetricAlgorithm;
+use sequoia_openpgp as openpgp;
+
+use super::{
+    KeyUnlocker,
+    Owner
+};
+use crate::input;
+
+/// Name of the passphrase-encrypted master key file.
+pub const FILE: &str = "masterKey.asc";
+
+/// How many times passphrase is asked before giving up.
+const MAX_ATTEMPTS: usize = 3;
+
+/// Asks passphrase on the terminal.
+pub struct Passphrase;
+
+/// Helper which decrypts message with a passphrase.
+struct Helper<'a> {
+    password: &'a Password
+}
+
+impl VerificationHelper for Helper<'_> {
+    fn get_certs(
+        &mut self,
+        _ids: &[openpgp::KeyHandle]
+    ) -> openpgp::Result<Vec<openpgp::Cert>> {
+        Ok(Vec::new())
+    }
+
+    fn check(
+        &mut self,
+        _structure: MessageStructure
+    ) -> openpgp::Result<()> {
+        Ok(())
+    }
+}
+
+impl DecryptionHelper for Helper<'_> {
+    fn decrypt(
+        &mut self,
+        _pkesks: &[PKESK],
+        skesks: &[SKESK],
+        _sym_algo: Option<SymmetricAlgorithm>,
+        decrypt: &mut dyn FnMut(Option<SymmetricAlgorithm>, &SessionKey) -> bool
+    ) -> openpgp::Result<Option<openpgp::Cert>> {
+        for skesk in skesks {
+            if let Ok((algo, session_key)) = skesk.decrypt(self.password)
+                && decrypt(algo, &session_key)
+            {
+                return Ok(None);
+            }
+        }
+        Err(openpgp::Error::InvalidPassword.into())
+    }
+}
+
+/// Decrypts OpenPGP `message` with `password`.
+pub fn decrypt_message(
+    message: &[u8],
+    password: &Password
+) -> Result<Vec<u8>> {
+    let policy = StandardPolicy::new();
+    let mut decryptor = DecryptorBuilder::from_bytes(message)
+        .and_then(|builder| builder.with_policy(&policy, None, Helper { password }))
+        .map_err(|err| eyre!("{err:#}"))?;
+
+    let mut plain = Vec::new();
+    decryptor
+        .read_to_end(&mut plain)
+        .context("Failed to read decrypted message")?;
+    Ok(plain)
+}
+
+/// Encrypts `plain` with `password` into an armored
+/// OpenPGP message.
+pub fn encrypt_message(
+    plain: &[u8],
+    password: &Password
+) -> Result<Vec<u8>> {
+    let mut message = Vec::new();
+    let mut writer = Armorer::new(Message::new(&mut message))
+        .kind(armor::Kind::Message)
+        .build()
+        .and_then(|armorer| Encryptor::with_passwords(armorer, Some(password.clone())).build())
+        .and_then(|encryptor| LiteralWriter::new(encryptor).build())
+        .map_err(|err| eyre!("{err:#}"))?;
+    writer
+        .write_all(plain)
+        .context("Failed to write OpenPGP message")?;
+    writer.finalize().map_err(|err| eyre!("{err:#}"))?;
+    Ok(message)
+}
+
+/// Encrypts new master key of `owner` with a passphrase
+/// asked on the terminal.
+pub fn lock(
+    owner: &Owner,
+    plain: &[u8]
+) -> Result<Vec<u8>> {
+    if !input::terminal() {
+        bail!("Passphrase for {owner} is required but no terminal is available");
+    }
+    let password: Password = dialoguer::Password::new()
+        .with_prompt(format!(
+            "New passphrase for {}",
+            owner.to_string().blue().bold()
+        ))
+        .with_confirmation("Repeat passphrase", "Passphrases do not match")
+        .interact()
+        .context("Failed to recieve input")?
+        .into();
+    encrypt_message(plain, &password)
+}
+
+impl KeyUnlocker for Passphrase {
+    fn file(&self) -> &'static str { FILE }
+
+    /// Passphrase is asked up to [`MAX_ATTEMPTS`] times.
+    fn unlock(
+        &self,
+        owner: &Owner,
+        encrypted: &[u8]
+    ) -> Result<Vec<u8>> {
+        if !input::terminal() {
+            bail!("Passphrase for {owner} is required but no terminal is available");
+        }
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let password: Password = dialoguer::Password::new()
+                .with_prompt(format!(
+                    "Passphrase for {}",
+                    owner.to_string().blue().bold()
+                ))
+                .interact()
+                .context("Failed to recieve input")?
+                .into();
+
+            match decrypt_message(encrypted, &password) {
+                Ok(plain) => return Ok(plain),
+                Err(err) if attempt < MAX_ATTEMPTS => {
+                    tracing::error!(
+                        "Failed to decrypt master key ({err}), {} attempts left",
+                        MAX_ATTEMPTS - attempt
+                    );
+                },
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!(
+                            "Failed to decrypt master key of {owner} in {MAX_ATTEMPTS} attempts"
+                        )
+                    });
+                }
+            }
+        }
+    }
+}
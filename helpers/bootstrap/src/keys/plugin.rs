@@ -0,0 +1,315 @@
+//! ## Plugin unlocker
+//! Master key encrypted with `age` to a hardware token,
+//! stored in `masterKey.age`.
+//!
+//! By default the token is asked through
+//! `age-plugin-fido2-hmac`, which derives the file key
+//! from the FIDO2 `hmac-secret` extension. Identities can
+//! be given explicitly instead, e.g. for another plugin or
+//! a plain `age` identity standing in for the
+//! authenticator.
+
+use std::collections::BTreeMap;
+use std::io::{
+    Read as _,
+    Write as _
+};
+use std::path::PathBuf;
+
+use age::plugin::{
+    Identity,
+    IdentityPluginV1,
+    RecipientPluginV1
+};
+use age::secrecy::SecretString;
+use age::{
+    Callbacks,
+    Decryptor,
+    Encryptor,
+    IdentityFile,
+    x25519
+};
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail,
+    eyre
+};
+use colored::Colorize as _;
+
+use super::{
+    KeyUnlocker,
+    Owner
+};
+use crate::input;
+
+/// Name of the token-encrypted master key file.
+pub const FILE: &str = "masterKey.age";
+
+/// Plugin used when no identities are given.
+pub const DEFAULT_PLUGIN: &str = "fido2-hmac";
+
+/// Unlocks master key with `age` identities.
+pub struct Plugin {
+    /// Identity file to use instead of the default plugin
+    pub identity: Option<PathBuf>
+}
+
+/// Forwards plugin requests to the terminal.
+#[derive(Clone)]
+struct Prompt;
+
+impl Callbacks for Prompt {
+    fn display_message(
+        &self,
+        message: &str
+    ) {
+        tracing::info!("{}", message.yellow());
+    }
+
+    fn confirm(
+        &self,
+        message: &str,
+        yes_string: &str,
+        no_string: Option<&str>
+    ) -> Option<bool> {
+        if !input::terminal() {
+            return None;
+        }
+        let options = [yes_string, no_string.unwrap_or("No")];
+        dialoguer::Select::new()
+            .with_prompt(message)
+            .items(options)
+            .default(0)
+            .interact()
+            .ok()
+            .map(|index| index == 0)
+    }
+
+    fn request_public_string(
+        &self,
+        description: &str
+    ) -> Option<String> {
+        if !input::terminal() {
+            return None;
+        }
+        dialoguer::Input::new()
+            .with_prompt(description)
+            .interact_text()
+            .ok()
+    }
+
+    fn request_passphrase(
+        &self,
+        description: &str
+    ) -> Option<SecretString> {
+        if !input::terminal() {
+            return None;
+        }
+        dialoguer::Password::new()
+            .with_prompt(description)
+            .interact()
+            .ok()
+            .map(SecretString::from)
+    }
+}
+
+impl Plugin {
+    /// Loads identities to decrypt with.
+    fn identities(&self) -> Result<Vec<Box<dyn age::Identity>>> {
+        match &self.identity {
+            Some(path) => IdentityFile::from_file(path.display().to_string())
+                .with_context(|| format!("Failed to read identities {}", path.display()))?
+                .with_callbacks(Prompt)
+                .into_identities()
+                .with_context(|| format!("Failed to parse identities {}", path.display())),
+            None => {
+                let plugin = IdentityPluginV1::new(
+                    DEFAULT_PLUGIN,
+                    &[Identity::default_for_plugin(DEFAULT_PLUGIN)],
+                    Prompt
+                )
+                .context("Failed to start age plugin")?;
+                Ok(vec![Box::new(plugin)])
+            }
+        }
+    }
+}
+
+impl KeyUnlocker for Plugin {
+    fn file(&self) -> &'static str { FILE }
+
+    fn unlock(
+        &self,
+        owner: &Owner,
+        encrypted: &[u8]
+    ) -> Result<Vec<u8>> {
+        let identities = self.identities()?;
+        tracing::info!("Unlocking master key of {owner}, touch the token if asked...");
+
+        let mut plain = Vec::new();
+        Decryptor::new_buffered(encrypted)
+            .and_then(|decryptor| decryptor.decrypt(identities.iter().map(|id| id.as_ref())))
+            .with_context(|| format!("Failed to decrypt master key of {owner}"))?
+            .read_to_end(&mut plain)
+            .context("Failed to read decrypted master key")?;
+        Ok(plain)
+    }
+}
+
+/// Encrypts new master key to `recipients`, e.g. the one
+/// printed by `age-plugin-fido2-hmac -g`.
+pub fn lock(
+    plain: &[u8],
+    recipients: &[String]
+) -> Result<Vec<u8>> {
+    if recipients.is_empty() {
+        bail!(
+            "Recipient of the token is required, generate one with age-plugin-{DEFAULT_PLUGIN} -g"
+        );
+    }
+
+    let mut parsed: Vec<Box<dyn age::Recipient>> = Vec::new();
+    let mut plugins = BTreeMap::<String, Vec<age::plugin::Recipient>>::new();
+    for recipient in recipients {
+        if let Ok(recipient) = recipient.parse::<x25519::Recipient>() {
+            parsed.push(Box::new(recipient));
+            continue;
+        }
+        let recipient = recipient
+            .parse::<age::plugin::Recipient>()
+            .map_err(|err| eyre!("Invalid age recipient {recipient}: {err}"))?;
+        plugins
+            .entry(recipient.plugin().to_owned())
+            .or_default()
+            .push(recipient);
+    }
+    for (name, recipients) in plugins {
+        let plugin = RecipientPluginV1::new(&name, &recipients, &[], Prompt)
+            .with_context(|| format!("Failed to start age plugin {name}"))?;
+        parsed.push(Box::new(plugin));
+    }
+
+    let mut encrypted = Vec::new();
+    let mut writer = Encryptor::with_recipients(parsed.iter().map(|recipient| recipient.as_ref()))
+        .context("Failed to encrypt master key")?
+        .wrap_output(&mut encrypted)
+        .context("Failed to encrypt master key")?;
+    writer
+        .write_all(plain)
+        .context("Failed to encrypt master key")?;
+    writer.finish().context("Failed to encrypt master key")?;
+    Ok(encrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use age::secrecy::ExposeSecret as _;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::keys::{
+        self,
+        passphrase
+    };
+
+    const PLAIN: &[u8] = b"AGE-SECRET-KEY-MASTER\n";
+
+    /// Writes new `age` identity into `dir`, returning its
+    /// path and recipient.
+    fn identity(dir: &Path) -> (PathBuf, String) {
+        let identity = x25519::Identity::generate();
+        let path = dir.join("identity.txt");
+        fs::write(&path, format!("{}\n", identity.to_string().expose_secret())).unwrap();
+        (path, identity.to_public().to_string())
+    }
+
+    /// Unlocker of passphrase keys which must not be used.
+    struct Unused;
+
+    impl KeyUnlocker for Unused {
+        fn file(&self) -> &'static str { passphrase::FILE }
+
+        fn unlock(
+            &self,
+            _owner: &Owner,
+            _encrypted: &[u8]
+        ) -> Result<Vec<u8>> {
+            bail!("Wrong unlocker")
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = TempDir::with_prefix("plugin").unwrap();
+        let (path, recipient) = identity(dir.path());
+
+        let encrypted = lock(PLAIN, &[recipient]).unwrap();
+        let plain = Plugin {
+            identity: Some(path)
+        }
+        .unlock(&Owner::Host("t".to_owned()), &encrypted)
+        .unwrap();
+        assert_eq!(plain, PLAIN);
+    }
+
+    #[test]
+    fn wrong_identity_fails() {
+        let dir = TempDir::with_prefix("plugin").unwrap();
+        let (path, _) = identity(dir.path());
+        let other = x25519::Identity::generate().to_public().to_string();
+
+        let encrypted = lock(PLAIN, &[other]).unwrap();
+        let unlocked = Plugin {
+            identity: Some(path)
+        }
+        .unlock(&Owner::Host("t".to_owned()), &encrypted);
+        assert!(unlocked.is_err());
+    }
+
+    #[test]
+    fn lock_requires_recipient() {
+        assert!(lock(PLAIN, &[]).is_err());
+    }
+
+    #[test]
+    fn decrypt_file_picks_unlocker_by_extension() {
+        let dir = TempDir::with_prefix("plugin").unwrap();
+        let (path, recipient) = identity(dir.path());
+        let unlockers: Vec<Box<dyn KeyUnlocker>> = vec![
+            Box::new(Unused),
+            Box::new(Plugin {
+                identity: Some(path)
+            }),
+        ];
+
+        let source = dir.path().join(FILE);
+        fs::write(&source, lock(PLAIN, &[recipient]).unwrap()).unwrap();
+        let output = dir.path().join("output");
+        let key =
+            keys::decrypt_file(&source, &output, Owner::User("u".to_owned()), &unlockers).unwrap();
+        assert_eq!(key.path, output.join("masterKey"));
+        assert_eq!(fs::read(&key.path).unwrap(), PLAIN);
+
+        let source = dir.path().join(passphrase::FILE);
+        fs::write(&source, b"").unwrap();
+        let Err(error) =
+            keys::decrypt_file(&source, &output, Owner::User("u".to_owned()), &unlockers)
+        else {
+            panic!("Decrypted {}", source.display());
+        };
+        assert_eq!(error.to_string(), "Wrong unlocker");
+
+        let source = dir.path().join("masterKey.asc.old");
+        fs::write(&source, b"").unwrap();
+        let Err(error) =
+            keys::decrypt_file(&source, &output, Owner::User("u".to_owned()), &unlockers)
+        else {
+            panic!("Decrypted {}", source.display());
+        };
+        assert!(error.to_string().starts_with("Unknown master key format"));
+    }
+}
@@ -1,159 +1,194 @@
 #![doc = include_str!("../README.md")]
 
-use {
-    niac_error as error,
-    niac_log as log
+use std::path::Path;
+use std::{
+    env,
+    io
 };
 
-use crate::sigint::TMPDIR;
-mod sigint;
+use niac_error as error;
+use niac_log as log;
 
-use std::env;
-use std::path::PathBuf;
-use std::thread::sleep;
-use std::time::Duration;
-
-use color_eyre::Result;
-use color_eyre::eyre::{
-    Context,
-    bail,
-    eyre
+use crate::cli::{
+    Command,
+    LogFormat
+};
+use crate::plan::Plan;
+use crate::stages::{
+    Context as StageContext,
+    Runner,
+    Start
 };
+use crate::state::State;
+use crate::workspace::SecretWorkspace;
+mod add;
+mod cli;
+mod command;
+mod flake;
+mod hardware;
+mod init;
+mod input;
+mod keys;
+mod plan;
+mod rekey;
+mod rules;
+mod secret;
+mod secureboot;
+mod signal;
+mod sops;
+mod stages;
+mod state;
+mod verify;
+mod workspace;
+
+use clap::Parser as _;
+use color_eyre::Result;
+use color_eyre::eyre::bail;
 use colored::Colorize as _;
-use tempdir::TempDir;
-use tracing::info;
+
+/// Name of the installation log. It is written to the
+/// temporary directory until the target root is mounted.
+pub const LOG_FILE: &str = "niac-bootstrap.log";
+
+/// Size of the installation log after which it is rotated.
+const LOG_SIZE: u64 = 16 * 1024 * 1024;
+
 fn main() -> Result<()> {
-    error::install()?;
-    log::install()?;
-    sigint::init()?;
+    let mut args = cli::Args::parse();
+    // Report of `verify-secrets` goes to stdout for CI, so
+    // logs have to leave it alone.
+    if let Some(Command::VerifySecrets(verify)) = &mut args.command
+        && matches!(args.log_format, LogFormat::Json)
+    {
+        verify.report.get_or_insert_with(|| verify::STDOUT.into());
+    }
+    let report = matches!(
+        &args.command,
+        Some(Command::VerifySecrets(verify))
+            if verify.report.as_deref() == Some(Path::new(verify::STDOUT))
+    );
+
+    let color = args.color.into();
+    error::install_with(color)?;
+    error::on_panic(workspace::wipe_all);
+    // Messages are colored with `colored` before reaching
+    // the logger, so it has to agree too.
+    colored::control::set_override(if report {
+        color.enabled(&io::stderr())
+    } else {
+        color.enabled(&io::stdout())
+    });
+
+    let mut logger = log::Builder::new()
+        .level(args.log_level())
+        .format(args.log_format.into())
+        .color(color)
+        .stderr(report);
+    if args.command.is_none() {
+        logger = logger.file(
+            env::temp_dir().join(LOG_FILE),
+            log::Rotation::Size(LOG_SIZE)
+        );
+    }
+    logger.install()?;
+    signal::init()?;
+
+    match args.command.take() {
+        Some(Command::Rekey(rekey)) => rekey::run(&flake::find(args.flake)?, rekey, args.identity),
+        Some(Command::VerifySecrets(verify)) =>
+            verify::run(&flake::find(args.flake)?, verify, args.identity),
+        Some(Command::Secrets(cli::Secrets::Add(add))) => {
+            let flake = flake::find(args.flake)?;
+            add::run(&flake.join("secrets"), add, args.identity)
+        },
+        Some(Command::Secrets(cli::Secrets::Secureboot(keys))) => {
+            let flake = flake::find(args.flake)?;
+            secureboot::run(&flake.join("secrets"), keys, args.identity)
+        },
+        Some(Command::InitHost(init)) => init::host(&flake::find(args.flake)?, init),
+        Some(Command::InitUser(init)) => init::user(&flake::find(args.flake)?, init),
+        None => install(args)
+    }
+}
+
+/// Installs NixOS, running every stage.
+fn install(args: cli::Args) -> Result<()> {
+    let plan = Plan::from_args(&args)?;
+    let state_path = args.state.unwrap_or_else(State::default_path);
+    let (start, mut state) = if args.resume {
+        let state = State::load(state_path)?;
+        state.check_resume(plan.host.as_deref(), &plan.users)?;
+        (Start::Resume, state)
+    } else if let Some(stage) = args.from_stage {
+        (Start::From(stage), State::load_or_new(state_path)?)
+    } else {
+        (Start::Beginning, State::new(state_path))
+    };
 
-    let (flake, output) = {
+    let (flake, secrets, output) = {
         let span = tracing::info_span!("dirs_setup");
         let _guard = span.enter();
 
-        let mut flake = if cfg!(feature = "nix-ready") {
-            PathBuf::from(env::var("NIaC_SELF").unwrap())
-        } else {
-            info!("Searching {}...", "flake".blue());
-            env::current_dir()
-                .context("Failed to find flake by $PWD")
-                .and_then(|mut pwd| {
-                    if pwd.join("flake.nix").exists() {
-                        return Ok(pwd);
-                    } else {
-                        info!(
-                            "Path \"{}\" does not contain a {}, searching up...",
-                            pwd.display().to_string().yellow(),
-                            "\"flake.nix\"".blue()
-                        );
-                        while !pwd.join("flake.nix").exists() {
-                            if !pwd.pop() {
-                                bail!("Failed to find flake root!");
-                            }
-                        }
-                        Ok(pwd)
-                    }
-                })?
-        };
-        tracing::info!("{} {}", "Flake:".blue().bold(), flake.display());
-        flake.push("secrets");
-
-        let output = TempDir::new("secrets")
-            .and_then(|tmp| {
-                *TMPDIR.lock().unwrap() = tmp.path().to_str().unwrap().into();
-                Ok(tmp)
-            })
-            .context("Failed to create temporary directory")?;
+        let flake = flake::find(plan.flake)?;
+        let secrets = flake.join("secrets");
+
+        let output = SecretWorkspace::new("secrets")?;
         tracing::info!("{} {}", "OUT:".blue().bold(), output.path().display());
 
-        (flake, output)
+        (flake, secrets, output)
     };
 
     let (host, users) = {
         let span = tracing::info_span!("input");
         let _guard = span.enter();
 
-        let host = loop {
-            let input = dialoguer::Input::<'_, String>::new()
-                .with_prompt("Host".blue().bold().underline().to_string())
-                .interact_text()
-                .map_err(|err| {
-                    sleep(Duration::from_millis(1));
-                    err
-                })
-                .context("Failed to recieve input")?;
-
-            if input.is_empty() {
-                tracing::error!("No hostname entered");
-                continue;
-            }
-            tracing::info!("Checking if host configuration exists...");
-            let dir = flake.join("hosts").join(&input);
-            if dir.exists() {
-                break input;
-            } else {
-                tracing::error!(
-                    "Folder {} {}",
-                    dir.to_string_lossy().underline(),
-                    "not found!".red().bold()
-                );
-                println!(
-                    "Hostname {} is {} Try again.",
-                    input.red().underline(),
-                    "invalid!".red().bold()
-                );
-            }
-        };
-
-        let users = loop {
-            let input = dialoguer::Input::<'_, String>::new()
-                .with_prompt("Users".blue().bold().underline().to_string())
-                .interact_text()
-                .context("Failed to recieve input")?
-                .split(' ')
-                .map(|s| s.into())
-                .collect::<Vec<String>>();
-
-            if input.is_empty() {
-                tracing::error!("No usernames entered");
-                continue;
-            }
-            tracing::info!("Checking if all users configurations exist...");
-
-            let mut invalid_users = Vec::<String>::new();
-            for user in &input {
-                let dir = flake.join("users").join(&user);
-                if dir.exists() {
-                    continue;
-                } else {
-                    tracing::error!(
-                        "Folder {} {}",
-                        dir.display().to_string().underline(),
-                        "not found!".red()
-                    );
-                    invalid_users.push(user.into());
-                }
-            }
-
-            if invalid_users.is_empty() {
-                break input;
+        let host = input::host(&secrets, plan.host.or(state.host.take()))?;
+        let users = input::users(
+            &secrets,
+            if plan.users.is_empty() {
+                std::mem::take(&mut state.users)
             } else {
-                println!(
-                    "Users {} are {} Try again.",
-                    invalid_users
-                        .iter()
-                        .map(|user| user.red().underline().to_string())
-                        .collect::<Vec<String>>()
-                        .join(", "),
-                    "invalid".red().bold()
-                )
+                plan.users
             }
-        };
+        )?;
+
+        if !input::confirm(
+            &format!(
+                "Install host {} with users {}?",
+                host.blue().bold(),
+                users.join(", ").blue().bold()
+            ),
+            plan.yes
+        )? {
+            bail!("Aborted by user");
+        }
 
         (host, users)
     };
 
-    tracing::info!("Hello, world!");
+    state.host = Some(host.clone());
+    state.users = users.clone();
+    let mut ctx = StageContext {
+        flake,
+        secrets,
+        output: output.path().to_path_buf(),
+        root: args.root,
+        sysroot: args.sysroot,
+        dry_run: args.dry_run,
+        identity: args.identity,
+        host,
+        users,
+        yes: plan.yes,
+        disks: if plan.disks.is_empty() {
+            std::mem::take(&mut state.disks)
+        } else {
+            plan.disks
+        },
+        plan: args.plan,
+        host_key: None,
+        user_keys: Vec::new()
+    };
+    Runner::new().run(&mut ctx, &mut state, &start)?;
 
     Ok(())
 }
@@ -0,0 +1,165 @@
+//! ## Plan
+//! Plan file is a TOML document holding the same answers
+//! that are otherwise given with CLI flags or prompts.
+//!
+//! ### Example
+//! ```toml
+//! host  = "jetstream"
+//! users = [ "root", "Sk7Str1p3" ]
+//! flake = "../.."
+//! yes   = true
+//!
+//! [disks]
+//! main = "nvme-Samsung_SSD_980_1TB_S64ANS0T000000"
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::{
+    env,
+    fs
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    OptionExt as _
+};
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+use crate::cli::Args;
+
+/// Answers for the bootstrap script.
+///
+/// Every field is optional: missing values are asked
+/// interactively, if possible.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    /// Host to install
+    pub host:  Option<String>,
+    /// Users to set up
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Path to the flake root
+    pub flake: Option<PathBuf>,
+    /// Skip confirmations. Never saved, so a plan written
+    /// by [`Plan::save`] still asks before wiping disks
+    #[serde(default, skip_serializing)]
+    pub yes:   bool,
+    /// Declared disk name to device in `/dev/disk/by-id`,
+    /// replacing the one of the configuration
+    #[serde(default)]
+    pub disks: BTreeMap<String, String>
+}
+
+impl Plan {
+    /// Where plan is saved if no `--plan` was given.
+    pub fn default_path() -> PathBuf { env::temp_dir().join("niac-bootstrap.plan.toml") }
+
+    /// Reads plan from a TOML file.
+    ///
+    /// Relative `flake` path is resolved against the
+    /// directory containing the plan.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read plan {}", path.display()))?;
+        let mut plan: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse plan {}", path.display()))?;
+
+        if let Some(flake) = &plan.flake
+            && flake.is_relative()
+            && let Some(dir) = path.parent()
+        {
+            plan.flake = Some(dir.join(flake));
+        }
+
+        Ok(plan)
+    }
+
+    /// Builds plan from CLI arguments, loading plan file if
+    /// it was passed. Flags take precedence over the file.
+    pub fn from_args(args: &Args) -> Result<Self> {
+        let plan = match &args.plan {
+            Some(path) => Self::load(path)?,
+            None => Self::default()
+        };
+
+        Ok(Self {
+            host:  args.host.clone().or(plan.host),
+            users: if args.users.is_empty() {
+                plan.users
+            } else {
+                args.users.clone()
+            },
+            flake: args.flake.clone().or(plan.flake),
+            yes:   args.yes || plan.yes,
+            disks: plan.disks
+        })
+    }
+
+    /// Writes plan to `path`, so installation can be run
+    /// again with the same answers.
+    pub fn save(
+        &self,
+        path: &Path
+    ) -> Result<()> {
+        let content = toml::to_string(self).context("Failed to serialize plan")?;
+        fs::write(path, content).with_context(|| format!("Failed to write plan {}", path.display()))
+    }
+
+    /// Sets `disks` in the `[disks]` table of the plan at
+    /// `path`, keeping the rest of the file with its
+    /// comments as written.
+    pub fn save_disks(
+        path: &Path,
+        disks: &BTreeMap<String, String>
+    ) -> Result<()> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read plan {}", path.display()))?;
+        let mut document = content
+            .parse::<toml_edit::DocumentMut>()
+            .with_context(|| format!("Failed to parse plan {}", path.display()))?;
+        let table = document
+            .entry("disks")
+            .or_insert_with(toml_edit::table)
+            .as_table_mut()
+            .ok_or_eyre("`disks` of the plan is not a table")?;
+        for (name, id) in disks {
+            table.insert(name, toml_edit::value(id.as_str()));
+        }
+        fs::write(path, document.to_string())
+            .with_context(|| format!("Failed to write plan {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn save_skips_yes() {
+        let dir = TempDir::with_prefix("plan").unwrap();
+        let path = dir.path().join("plan.toml");
+        Plan {
+            host: Some("jetstream".into()),
+            yes: true,
+            ..Default::default()
+        }
+        .save(&path)
+        .unwrap();
+
+        assert!(!fs::read_to_string(&path).unwrap().contains("yes"));
+        let plan = Plan::load(&path).unwrap();
+        assert_eq!(plan.host.as_deref(), Some("jetstream"));
+        assert!(!plan.yes);
+    }
+}
@@ -0,0 +1,218 @@
+//! ## Rekey
+//! Re-encrypts SOPS documents of a host or user for a new
+//! set of recipients.
+//!
+//! Documents are decrypted with the old master key and
+//! encrypted again with a fresh data key, so the old key
+//! can not read new versions of them. Creation rule of the
+//! owner in `.sops.yaml` is updated to match.
+//!
+//! New versions are staged in [`STAGING`] first and then
+//! renamed into place, so a failed rekey changes nothing
+//! and an interrupted one is finished by the next run.
+
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use colored::Colorize as _;
+
+use crate::keys::{
+    self,
+    MasterKey,
+    Owner
+};
+use crate::workspace::SecretWorkspace;
+use crate::{
+    cli,
+    rules,
+    sops
+};
+
+/// Folder of the flake where new versions of documents and
+/// `.sops.yaml` are written before replacing the old ones.
+pub const STAGING: &str = ".rekey";
+
+/// Marker in [`STAGING`] written once every file is staged.
+const READY: &str = "ready";
+
+/// Writes documents of `dir`, re-encrypted for
+/// `recipients`, into `staging`, with the same paths
+/// relative to `flake`.
+///
+/// Returns number of staged documents.
+fn stage(
+    flake: &Path,
+    staging: &Path,
+    dir: &Path,
+    old: &MasterKey,
+    recipients: &[String]
+) -> Result<usize> {
+    fs::create_dir_all(staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+    let mut changed = 0;
+    for path in sops::walk(dir)? {
+        if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(keys::is_master_key)
+        {
+            continue;
+        }
+        let relative = path.strip_prefix(flake)?;
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if !sops::is_document(&content) {
+            continue;
+        }
+
+        let previous = sops::recipients(&content)?;
+        if previous == recipients {
+            tracing::info!("{} {}", "Unchanged:".bold(), relative.display());
+            continue;
+        }
+
+        let map = sops::decrypt(&content, old)
+            .with_context(|| format!("Failed to decrypt {}", relative.display()))?
+            .into_inner_map();
+        let encrypted = sops::encrypt(map, recipients)
+            .with_context(|| format!("Failed to encrypt {}", relative.display()))?;
+        let target = staging.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&target, encrypted)
+            .with_context(|| format!("Failed to write {}", target.display()))?;
+
+        tracing::info!("{} {}", "Staged:".yellow().bold(), relative.display());
+        for recipient in previous.iter().filter(|old| !recipients.contains(old)) {
+            tracing::info!("  {}", format!("- {recipient}").red());
+        }
+        for recipient in recipients.iter().filter(|new| !previous.contains(new)) {
+            tracing::info!("  {}", format!("+ {recipient}").green());
+        }
+        changed += 1;
+    }
+    Ok(changed)
+}
+
+/// Moves every file staged in `staging` to its place in
+/// `flake` and removes `staging`.
+///
+/// Each file is renamed, so it is either the old or the new
+/// version. If interrupted, the rest is moved by the next
+/// run.
+fn commit(
+    flake: &Path,
+    staging: &Path
+) -> Result<()> {
+    for path in sops::walk(staging)? {
+        let relative = path.strip_prefix(staging)?;
+        if relative == Path::new(READY) {
+            continue;
+        }
+        let target = flake.join(relative);
+        fs::rename(&path, &target).with_context(|| {
+            format!("Failed to move {} to {}", path.display(), target.display())
+        })?;
+    }
+    fs::remove_dir_all(staging).with_context(|| format!("Failed to remove {}", staging.display()))
+}
+
+/// Re-encrypts secrets of the owner given in `args` and
+/// points its creation rule to the new recipients.
+pub fn run(
+    flake: &Path,
+    args: cli::Rekey,
+    identity: Option<PathBuf>
+) -> Result<()> {
+    let owner = Owner::from(args.owner);
+    let span = tracing::info_span!("rekey", %owner);
+    let _guard = span.enter();
+
+    let staging = flake.join(STAGING);
+    if staging.join(READY).exists() {
+        tracing::warn!(
+            "Previous rekey was interrupted, finishing it from {}",
+            staging.display()
+        );
+        commit(flake, &staging)?;
+    } else if staging.exists() {
+        tracing::warn!(
+            "Previous rekey was interrupted before anything changed, removing {}",
+            staging.display()
+        );
+        fs::remove_dir_all(&staging)
+            .with_context(|| format!("Failed to remove {}", staging.display()))?;
+    }
+
+    let secrets = flake.join("secrets");
+    let dir = secrets.join(owner.dir());
+    if !dir.is_dir() {
+        bail!("Folder {} not found!", dir.display());
+    }
+
+    let workspace = SecretWorkspace::new("rekey")?;
+    let unlockers = keys::unlockers(identity);
+    let current = (args.old.is_none() || args.recipients.is_empty())
+        .then(|| {
+            keys::decrypt(
+                &secrets,
+                &workspace.path().join("current"),
+                owner.clone(),
+                &unlockers
+            )
+        })
+        .transpose()?;
+
+    let old = match (&args.old, &current) {
+        (Some(path), _) => keys::decrypt_file(
+            path,
+            &workspace.path().join("old"),
+            owner.clone(),
+            &unlockers
+        )?,
+        (None, Some(key)) => key.clone(),
+        (None, None) => unreachable!("current key is decrypted without --old")
+    };
+    let mut recipients = match current {
+        Some(key) if args.recipients.is_empty() => key.recipients()?,
+        _ => args.recipients
+    };
+    recipients.sort();
+    recipients.dedup();
+    // Fails before any document is touched if the rule can
+    // not be updated.
+    let registration = rules::update(flake, &owner, &recipients)?;
+
+    let staged = stage(flake, &staging, &dir, &old, &recipients).and_then(|changed| {
+        registration.save_in(&staging)?;
+        fs::write(staging.join(READY), "")
+            .with_context(|| format!("Failed to write {}", staging.display()))?;
+        Ok(changed)
+    });
+    let changed = match staged {
+        Ok(changed) => changed,
+        Err(err) => {
+            if let Err(err) = fs::remove_dir_all(&staging) {
+                tracing::warn!("Failed to remove {}: {err}", staging.display());
+            }
+            return Err(err);
+        }
+    };
+    commit(flake, &staging)?;
+
+    tracing::info!(
+        "{} {changed} documents of {owner} rekeyed",
+        "Done:".green().bold()
+    );
+    Ok(())
+}
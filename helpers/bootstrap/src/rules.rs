@@ -0,0 +1,532 @@
+//! ## Rules
+//! Creation rules of `.sops.yaml`, which tell recipients
+//! every SOPS document is expected to be encrypted for.
+//!
+//! New owners are registered by editing the file as text,
+//! so its anchors and comments are kept.
+
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::keys::Owner;
+use crate::sops;
+
+/// Name of the SOPS configuration, in the flake root.
+pub const FILE: &str = ".sops.yaml";
+
+/// Recipients of a single key group.
+#[derive(Deserialize)]
+struct KeyGroup {
+    #[serde(default)]
+    age: Vec<String>
+}
+
+/// Single creation rule.
+#[derive(Deserialize)]
+struct Rule {
+    path_regex: Option<String>,
+    /// Comma-separated recipients, alternative to groups
+    age:        Option<String>,
+    #[serde(default)]
+    key_groups: Vec<KeyGroup>
+}
+
+/// Contents of `.sops.yaml` used by bootstrap.
+#[derive(Deserialize)]
+struct Config {
+    /// Missing or empty, e.g. before the first rule is
+    /// registered
+    creation_rules: Option<Vec<Rule>>
+}
+
+/// Parsed creation rules, in order.
+pub struct Rules {
+    rules: Vec<(Option<Regex>, Vec<String>)>
+}
+
+impl Rules {
+    /// Loads rules of the flake at `flake`.
+    pub fn load(flake: &Path) -> Result<Self> {
+        let path = flake.join(FILE);
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&content, &path)
+    }
+
+    /// Parses `content` of the configuration at `path`.
+    fn parse(
+        content: &str,
+        path: &Path
+    ) -> Result<Self> {
+        let config: Config = serde_yaml::from_str(content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        let mut rules = Vec::new();
+        for rule in config.creation_rules.unwrap_or_default() {
+            let regex = rule
+                .path_regex
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .with_context(|| format!("Invalid path_regex in {}", path.display()))?;
+
+            let mut recipients = rule
+                .age
+                .iter()
+                .flat_map(|age| age.split(','))
+                .map(|recipient| recipient.trim().to_owned())
+                .chain(rule.key_groups.into_iter().flat_map(|group| group.age))
+                .filter(|recipient| !recipient.is_empty())
+                .collect::<Vec<_>>();
+            recipients.sort();
+            recipients.dedup();
+            rules.push((regex, recipients));
+        }
+        Ok(Self { rules })
+    }
+
+    /// Returns sorted recipients of the first rule matching
+    /// `path`, relative to the flake root.
+    pub fn recipients(
+        &self,
+        path: &Path
+    ) -> Option<&[String]> {
+        let path = path.to_string_lossy();
+        self.rules
+            .iter()
+            .find(|(regex, _)| regex.as_ref().is_none_or(|regex| regex.is_match(&path)))
+            .map(|(_, recipients)| recipients.as_slice())
+    }
+}
+
+/// `.sops.yaml` with a new owner, checked but not written
+/// yet.
+pub struct Registration {
+    path:      PathBuf,
+    owner:     Owner,
+    recipient: String,
+    /// `None` if the file is unchanged
+    content:   Option<String>
+}
+
+impl Registration {
+    /// Writes the new `.sops.yaml`.
+    pub fn save(self) -> Result<()> {
+        let path = self.path.clone();
+        self.write(&path)
+    }
+
+    /// Writes the new `.sops.yaml` into `root` instead of
+    /// the flake root, to be moved there later.
+    pub fn save_in(
+        self,
+        root: &Path
+    ) -> Result<()> {
+        self.write(&root.join(FILE))
+    }
+
+    fn write(
+        self,
+        path: &Path
+    ) -> Result<()> {
+        let Some(content) = self.content else {
+            return Ok(());
+        };
+        fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+        tracing::info!("Registered {} of {} in {FILE}", self.recipient, self.owner);
+        Ok(())
+    }
+}
+
+/// Adds `recipient` of `owner` to the keys of the flake at
+/// `flake` and a creation rule for the folder of the owner.
+/// The result is written with [`Registration::save`].
+///
+/// Nothing is changed if a rule for the folder exists
+/// already and lists `recipient`, otherwise it is an error,
+/// since documents of the owner would not be readable with
+/// the new key.
+pub fn register(
+    flake: &Path,
+    owner: &Owner,
+    recipient: &str
+) -> Result<Registration> {
+    let path = flake.join(FILE);
+    let content = if path.exists() {
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?
+    } else {
+        "keys:\ncreation_rules:\n".to_owned()
+    };
+
+    let dir = Path::new("secrets").join(owner.dir());
+    let document = dir.join(format!("document.{}", sops::EXTENSION));
+    if let Some((regex, recipients)) =
+        Rules::parse(&content, &path)?
+            .rules
+            .iter()
+            .find(|(regex, _)| {
+                regex
+                    .as_ref()
+                    .is_some_and(|regex| regex.is_match(&document.to_string_lossy()))
+            })
+    {
+        let regex = regex.as_ref().map(Regex::as_str).unwrap_or_default();
+        if !recipients.iter().any(|existing| existing == recipient) {
+            bail!(
+                "{FILE} has a rule for {} already ({regex}) without {recipient}, add it to the \
+                 rule or remove the rule",
+                dir.display()
+            );
+        }
+        tracing::info!(
+            "{FILE} has a rule for {} already ({regex}) with {recipient}, not changed",
+            dir.display()
+        );
+        return Ok(Registration {
+            path,
+            owner: owner.clone(),
+            recipient: recipient.to_owned(),
+            content: None
+        });
+    }
+
+    let (label, name, next_group) = match owner {
+        Owner::Host(name) => ("Host", name, Some("# Users")),
+        Owner::User(name) => ("User", name, None)
+    };
+    if content.contains(&format!("&{name} ")) {
+        bail!("Anchor &{name} already exists in {FILE}");
+    }
+
+    let mut lines = content.lines().map(str::to_owned).collect::<Vec<_>>();
+    let Some(rules) = lines
+        .iter()
+        .position(|line| line.starts_with("creation_rules:"))
+    else {
+        bail!("{FILE} has no creation_rules");
+    };
+    // Keys are grouped by comments, hosts before users.
+    let mut index = next_group
+        .and_then(|group| lines[..rules].iter().position(|line| line.trim() == group))
+        .unwrap_or(rules);
+    while index > 0 && lines[index - 1].trim().is_empty() {
+        index -= 1;
+    }
+    lines.insert(index, format!("  - &{name} {recipient}"));
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    lines.extend([
+        format!("  # {label}: {name}"),
+        format!(
+            "  - path_regex: ^{}/.*\\.(age|txt)$",
+            regex::escape(&dir.to_string_lossy())
+        ),
+        "    key_groups:".to_owned(),
+        "      - age:".to_owned(),
+        format!("          - *{name}")
+    ]);
+    let content = lines.join("\n") + "\n";
+
+    let recipients = Rules::parse(&content, &path)?
+        .recipients(&document)
+        .map(<[String]>::to_vec);
+    if recipients.as_deref() != Some(&[recipient.to_owned()]) {
+        bail!("Failed to add rule for {} to {FILE}", dir.display());
+    }
+    Ok(Registration {
+        path,
+        owner: owner.clone(),
+        recipient: recipient.to_owned(),
+        content: Some(content)
+    })
+}
+
+/// Points the creation rule of `owner` in the flake at
+/// `flake` to sorted `recipients`, e.g. after the master
+/// key was rotated. The result is written with
+/// [`Registration::save`].
+///
+/// Only the anchor of the owner, as added by [`register`],
+/// is rewritten, and only to a single recipient. Any other
+/// layout has to be edited by hand.
+pub fn update(
+    flake: &Path,
+    owner: &Owner,
+    recipients: &[String]
+) -> Result<Registration> {
+    let path = flake.join(FILE);
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let dir = Path::new("secrets").join(owner.dir());
+    let document = dir.join(format!("document.{}", sops::EXTENSION));
+    let Some(current) = Rules::parse(&content, &path)?
+        .recipients(&document)
+        .map(<[String]>::to_vec)
+    else {
+        bail!("{FILE} has no rule for {}, add one by hand", dir.display());
+    };
+    let registration = |content| Registration {
+        path: path.clone(),
+        owner: owner.clone(),
+        recipient: recipients.join(", "),
+        content
+    };
+    if current == recipients {
+        return Ok(registration(None));
+    }
+
+    let manually = || {
+        format!(
+            "{FILE} must be edited by hand, so the rule for {} lists {}",
+            dir.display(),
+            recipients.join(", ")
+        )
+    };
+    let [recipient] = recipients else {
+        bail!(manually());
+    };
+    let (Owner::Host(name) | Owner::User(name)) = owner;
+    let anchor = format!("- &{name} ");
+    let mut lines = content.lines().map(str::to_owned).collect::<Vec<_>>();
+    let mut anchors = lines
+        .iter_mut()
+        .filter(|line| line.trim_start().starts_with(&anchor));
+    let (Some(line), None) = (anchors.next(), anchors.next()) else {
+        bail!(manually());
+    };
+    let indent = line.len() - line.trim_start().len();
+    *line = format!("{}{anchor}{recipient}", &line[..indent]);
+    let content = lines.join("\n") + "\n";
+
+    if Rules::parse(&content, &path)?.recipients(&document) != Some(recipients) {
+        bail!(manually());
+    }
+    Ok(registration(Some(content)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use age::x25519;
+    use serde_yaml::Value;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// `.sops.yaml` of this repository.
+    const REPO: &str = include_str!("../../../.sops.yaml");
+
+    /// Recipient of `jetstream` in [`REPO`].
+    const JETSTREAM: &str = "age1sern5cgzjgjwluesyjylygrd3473ytcr32xe2yqze28gyean7uqqxksgu8";
+
+    fn flake() -> TempDir {
+        let flake = TempDir::with_prefix("rules").unwrap();
+        fs::write(flake.path().join(FILE), REPO).unwrap();
+        flake
+    }
+
+    fn recipient() -> String { x25519::Identity::generate().to_public().to_string() }
+
+    /// Parses `content` as YAML, returning its creation
+    /// rules.
+    fn creation_rules(content: &str) -> Vec<Value> {
+        let value: Value = serde_yaml::from_str(content).unwrap();
+        let value: Value = serde_yaml::from_str(&serde_yaml::to_string(&value).unwrap()).unwrap();
+        value["creation_rules"].as_sequence().unwrap().clone()
+    }
+
+    fn recipients(
+        content: &str,
+        owner: &Owner
+    ) -> Option<Vec<String>> {
+        let document = Path::new("secrets").join(owner.dir()).join("document.age");
+        Rules::parse(content, Path::new(FILE))
+            .unwrap()
+            .recipients(&document)
+            .map(<[String]>::to_vec)
+    }
+
+    /// Checks that owners of [`REPO`] keep their
+    /// recipients.
+    fn assert_unchanged(
+        content: &str,
+        except: &str
+    ) {
+        let repo = [
+            Owner::Host("jetstream".into()),
+            Owner::Host("test".into()),
+            Owner::User("Sk7Str1p3".into()),
+            Owner::User("root".into())
+        ];
+        for owner in repo {
+            let (Owner::Host(name) | Owner::User(name)) = &owner;
+            if name != except {
+                assert_eq!(recipients(content, &owner), recipients(REPO, &owner));
+            }
+        }
+    }
+
+    #[test]
+    fn registers_host() {
+        let flake = flake();
+        let owner = Owner::Host("falcon".into());
+        let recipient = recipient();
+
+        let content = register(flake.path(), &owner, &recipient)
+            .unwrap()
+            .content
+            .unwrap();
+        assert_eq!(recipients(&content, &owner), Some(vec![recipient.clone()]));
+        assert_unchanged(&content, "falcon");
+        assert_eq!(
+            creation_rules(&content).len(),
+            creation_rules(REPO).len() + 1
+        );
+
+        // Hosts are listed before the users.
+        let lines = content.lines().collect::<Vec<_>>();
+        let anchor = lines
+            .iter()
+            .position(|line| *line == format!("  - &falcon {recipient}"))
+            .unwrap();
+        assert_eq!(lines[anchor - 1], REPO.lines().nth(3).unwrap());
+        assert_eq!(lines[anchor + 1], "  # Users");
+        assert!(content.ends_with("          - *falcon\n"));
+    }
+
+    #[test]
+    fn registers_user() {
+        let flake = flake();
+        let owner = Owner::User("alice".into());
+        let recipient = recipient();
+
+        let content = register(flake.path(), &owner, &recipient)
+            .unwrap()
+            .content
+            .unwrap();
+        assert_eq!(recipients(&content, &owner), Some(vec![recipient.clone()]));
+        assert_unchanged(&content, "alice");
+        assert_eq!(
+            creation_rules(&content).len(),
+            creation_rules(REPO).len() + 1
+        );
+
+        let lines = content.lines().collect::<Vec<_>>();
+        let anchor = lines
+            .iter()
+            .position(|line| *line == format!("  - &alice {recipient}"))
+            .unwrap();
+        assert_eq!(lines[anchor + 1], "creation_rules:");
+    }
+
+    #[test]
+    fn registers_into_missing_file() {
+        let flake = TempDir::with_prefix("rules").unwrap();
+        let owner = Owner::Host("falcon".into());
+        let recipient = recipient();
+
+        let registration = register(flake.path(), &owner, &recipient).unwrap();
+        let content = registration.content.clone().unwrap();
+        assert_eq!(recipients(&content, &owner), Some(vec![recipient]));
+        assert_eq!(creation_rules(&content).len(), 1);
+
+        registration.save().unwrap();
+        assert_eq!(
+            fs::read_to_string(flake.path().join(FILE)).unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn refuses_existing_anchor() {
+        let flake = flake();
+        // `&root` belongs to the user, host has no rule yet.
+        let error = register(flake.path(), &Owner::Host("root".into()), &recipient())
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Anchor &root already exists in .sops.yaml"
+        );
+    }
+
+    #[test]
+    fn keeps_existing_rule() {
+        let flake = flake();
+        let owner = Owner::Host("jetstream".into());
+
+        let registration = register(flake.path(), &owner, JETSTREAM).unwrap();
+        assert!(registration.content.is_none());
+
+        let error = register(flake.path(), &owner, &recipient()).err().unwrap();
+        assert!(error.to_string().contains("without"));
+    }
+
+    #[test]
+    fn updates_recipient() {
+        let flake = flake();
+        let owner = Owner::Host("jetstream".into());
+        let recipient = recipient();
+
+        let content = update(flake.path(), &owner, std::slice::from_ref(&recipient))
+            .unwrap()
+            .content
+            .unwrap();
+        assert_eq!(recipients(&content, &owner), Some(vec![recipient.clone()]));
+        assert_unchanged(&content, "jetstream");
+        assert_eq!(creation_rules(&content).len(), creation_rules(REPO).len());
+        assert_eq!(
+            content,
+            REPO.replace(
+                &format!("&jetstream {JETSTREAM}"),
+                &format!("&jetstream {recipient}")
+            )
+        );
+    }
+
+    #[test]
+    fn update_keeps_matching_rule() {
+        let flake = flake();
+        let owner = Owner::Host("jetstream".into());
+
+        let registration = update(flake.path(), &owner, &[JETSTREAM.to_owned()]).unwrap();
+        assert!(registration.content.is_none());
+    }
+
+    #[test]
+    fn update_refuses_other_layouts() {
+        let flake = flake();
+
+        let mut two = vec![recipient(), recipient()];
+        two.sort();
+        assert!(
+            update(flake.path(), &Owner::Host("jetstream".into()), &two)
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("must be edited by hand")
+        );
+        assert!(
+            update(flake.path(), &Owner::Host("falcon".into()), &[recipient()])
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("has no rule")
+        );
+    }
+}
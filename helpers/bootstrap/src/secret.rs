@@ -0,0 +1,50 @@
+//! ## Secret
+//! Helpers for writing decrypted material to disk.
+
+use std::fs::{
+    self,
+    OpenOptions,
+    Permissions
+};
+use std::io::Write as _;
+use std::os::unix::fs::{
+    OpenOptionsExt as _,
+    PermissionsExt as _
+};
+use std::path::Path;
+
+use color_eyre::Result;
+use color_eyre::eyre::Context as _;
+use colored::Colorize as _;
+
+/// Logs `path` instead of writing it, for `--dry-run`.
+pub fn dry_run(path: &Path) {
+    tracing::info!("{} {}", "Would write:".yellow().bold(), path.display());
+}
+
+/// Writes `content` to `path`, readable only by the owner.
+///
+/// Missing parent directories are created. Mode of an
+/// existing file is reset to `0600` before anything is
+/// written to it.
+pub fn write(
+    path: &Path,
+    content: &[u8]
+) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.set_permissions(Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to set mode of {}", path.display()))?;
+    file.write_all(content)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
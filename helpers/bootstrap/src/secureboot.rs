@@ -0,0 +1,446 @@
+//! ## SecureBoot keys
+//! Generates the SecureBoot hierarchy of a new host, the
+//! same way `sbctl create-keys` does.
+//!
+//! Every key of [`HIERARCHY`] is an RSA key with a
+//! self-signed certificate for digital signatures, stored
+//! as PKCS#8 and X.509 PEM. Files are encrypted into
+//! `secrets/hosts/<host>/secureBootKeys/` in the
+//! [`layout`] read by the SecureBoot stage.
+
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::time::{
+    Duration,
+    SystemTime
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail,
+    eyre
+};
+use colored::Colorize as _;
+use der::asn1::{
+    Any,
+    BitStringRef,
+    Null,
+    ObjectIdentifier,
+    OctetStringRef,
+    PrintableStringRef,
+    UintRef,
+    UtcTime,
+    Utf8StringRef
+};
+use der::pem::LineEnding;
+use der::{
+    DateTime,
+    Encode as _,
+    Tag,
+    TagNumber
+};
+use rand::RngCore as _;
+use rand::rngs::OsRng;
+use rsa::RsaPrivateKey;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::{
+    EncodePrivateKey as _,
+    EncodePublicKey as _
+};
+use rsa::signature::{
+    SignatureEncoding as _,
+    Signer as _
+};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::keys::Owner;
+use crate::stages::secureboot::{
+    HIERARCHY,
+    KEYS_DIR,
+    layout
+};
+use crate::{
+    add,
+    cli,
+    sops
+};
+
+/// Size of generated RSA keys, as used by `sbctl`.
+const KEY_BITS: usize = 4096;
+
+/// Lifetime of generated certificates.
+const VALIDITY: Duration = Duration::from_secs(20 * 365 * 24 * 60 * 60);
+
+/// `sha256WithRSAEncryption`
+const SHA256_WITH_RSA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+
+/// `countryName` attribute of a name.
+const COUNTRY: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.6");
+
+/// `commonName` attribute of a name.
+const COMMON_NAME: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.4.3");
+
+/// `keyUsage` extension of a certificate.
+const KEY_USAGE: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.29.15");
+
+/// `digitalSignature`, the first bit of key usage. Keys of
+/// the hierarchy only sign updates of the level below.
+const DIGITAL_SIGNATURE: u8 = 0x80;
+
+/// Returns common name of certificate of `key`.
+fn description(key: &str) -> &'static str {
+    match key {
+        "PK" => "Platform Key",
+        "KEK" => "Key Exchange Key",
+        _ => "Database Key"
+    }
+}
+
+/// Encodes `content` with a constructed `tag`.
+fn constructed(
+    tag: Tag,
+    content: &[&[u8]]
+) -> der::Result<Vec<u8>> {
+    Any::new(tag, content.concat())?.to_der()
+}
+
+/// Encodes `content` as a `SEQUENCE`.
+fn sequence(content: &[&[u8]]) -> der::Result<Vec<u8>> { constructed(Tag::Sequence, content) }
+
+/// Encodes name with country `WW` and `common_name`.
+fn name(common_name: &str) -> der::Result<Vec<u8>> {
+    let country = sequence(&[
+        &COUNTRY.to_der()?,
+        &PrintableStringRef::new("WW")?.to_der()?
+    ])?;
+    let common_name = sequence(&[
+        &COMMON_NAME.to_der()?,
+        &Utf8StringRef::new(common_name)?.to_der()?
+    ])?;
+    sequence(&[
+        &constructed(Tag::Set, &[&country])?,
+        &constructed(Tag::Set, &[&common_name])?
+    ])
+}
+
+/// Creates certificate of `key` signed by itself.
+fn certificate(
+    key: &RsaPrivateKey,
+    common_name: &str
+) -> Result<Vec<u8>> {
+    let mut serial = [0; 16];
+    OsRng.fill_bytes(&mut serial);
+    serial[0] &= 0x7f;
+    serial[0] |= 0x01;
+
+    let now = SystemTime::now();
+    let not_before = UtcTime::from_system_time(now)?;
+    let not_after = UtcTime::from_date_time(DateTime::from_system_time(now + VALIDITY)?)?;
+
+    let algorithm = sequence(&[&SHA256_WITH_RSA.to_der()?, &Null.to_der()?])?;
+    let name = name(common_name)?;
+    let public_key = key
+        .to_public_key()
+        .to_public_key_der()
+        .map_err(|err| eyre!("{err}"))?;
+
+    let version = constructed(
+        Tag::ContextSpecific {
+            constructed: true,
+            number:      TagNumber::N0
+        },
+        &[&2u8.to_der()?]
+    )?;
+    let key_usage = sequence(&[
+        &KEY_USAGE.to_der()?,
+        &true.to_der()?,
+        &OctetStringRef::new(&BitStringRef::new(7, &[DIGITAL_SIGNATURE])?.to_der()?)?.to_der()?
+    ])?;
+    let extensions = constructed(
+        Tag::ContextSpecific {
+            constructed: true,
+            number:      TagNumber::N3
+        },
+        &[&sequence(&[&key_usage])?]
+    )?;
+    let tbs = sequence(&[
+        &version,
+        &UintRef::new(&serial)?.to_der()?,
+        &algorithm,
+        &name,
+        &sequence(&[&not_before.to_der()?, &not_after.to_der()?])?,
+        &name,
+        public_key.as_bytes(),
+        &extensions
+    ])?;
+
+    let signature = SigningKey::<Sha256>::new(key.clone()).sign(&tbs).to_vec();
+    Ok(sequence(&[
+        &tbs,
+        &algorithm,
+        &BitStringRef::from_bytes(&signature)?.to_der()?
+    ])?)
+}
+
+/// Generates key of the hierarchy, returning private key
+/// and certificate as PEM.
+fn generate(key: &str) -> Result<(String, String)> {
+    tracing::info!("Generating {key} key...");
+    let private = RsaPrivateKey::new(&mut OsRng, KEY_BITS)
+        .with_context(|| format!("Failed to generate {key} key"))?;
+
+    let pem = private
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|err| eyre!("{err}"))?
+        .to_string();
+    let certificate = certificate(&private, description(key))
+        .with_context(|| format!("Failed to create {key} certificate"))?;
+    let certificate = der::pem::encode_string("CERTIFICATE", LineEnding::LF, &certificate)
+        .map_err(|err| eyre!("{err}"))?;
+    Ok((pem, certificate))
+}
+
+/// Generates SecureBoot keys of the host given in `args`.
+pub fn run(
+    secrets: &Path,
+    args: cli::SecureBootKeys,
+    identity: Option<PathBuf>
+) -> Result<()> {
+    let owner = Owner::Host(args.host);
+    let span = tracing::info_span!("secureboot", %owner);
+    let _guard = span.enter();
+
+    let host = secrets.join(owner.dir());
+    if !host.is_dir() {
+        bail!("Folder {} not found!", host.display());
+    }
+    let dir = host.join(KEYS_DIR);
+    for (file, _) in layout() {
+        add::ensure_writable(&dir.join(file), args.force)?;
+    }
+    let recipients = add::recipients(secrets, &owner, args.recipients, identity)?;
+
+    let mut files = vec![("GUID".to_owned(), Uuid::new_v4().to_string())];
+    for key in HIERARCHY {
+        let (pem, certificate) = generate(key)?;
+        files.push((format!("{key}/key"), pem));
+        files.push((format!("{key}/pem"), certificate));
+    }
+
+    for (file, content) in files {
+        let path = dir.join(file).with_extension(sops::EXTENSION);
+        add::write(&path, content, &recipients, args.force)?;
+        tracing::info!(
+            "{} {}",
+            "Generated:".green().bold(),
+            path.strip_prefix(secrets)?.display()
+        );
+    }
+    tracing::info!(
+        "{} SecureBoot keys of {owner} encrypted for {}",
+        "Done:".green().bold(),
+        recipients.join(", ")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use der::asn1::{
+        Any,
+        BitString,
+        ContextSpecific,
+        OctetString,
+        PrintableStringRef,
+        Uint,
+        UtcTime,
+        Utf8StringRef
+    };
+    use der::{
+        Reader as _,
+        SliceReader,
+        Tagged as _
+    };
+    use rsa::RsaPublicKey;
+    use rsa::pkcs1v15::{
+        Signature,
+        VerifyingKey
+    };
+    use rsa::pkcs8::DecodePublicKey as _;
+    use rsa::signature::Verifier as _;
+
+    use super::*;
+
+    /// Size of keys in tests, smaller than [`KEY_BITS`] to
+    /// keep them fast.
+    const TEST_BITS: usize = 2048;
+
+    /// Fields of a decoded certificate.
+    struct Decoded {
+        /// Encoded `tbsCertificate`, which is signed
+        tbs:           Vec<u8>,
+        version:       u8,
+        serial:        Uint,
+        algorithm:     Any,
+        tbs_algorithm: Any,
+        issuer:        Vec<(ObjectIdentifier, String)>,
+        not_before:    UtcTime,
+        not_after:     UtcTime,
+        subject:       Vec<(ObjectIdentifier, String)>,
+        public_key:    Vec<u8>,
+        /// `(extnID, critical, extnValue)`
+        extensions:    Vec<(ObjectIdentifier, bool, Vec<u8>)>,
+        signature:     BitString
+    }
+
+    /// Decodes attributes of a name.
+    fn attributes(name: &Any) -> der::Result<Vec<(ObjectIdentifier, String)>> {
+        let mut reader = SliceReader::new(name.value())?;
+        let mut attributes = Vec::new();
+        while !reader.is_finished() {
+            let set = reader.decode::<Any>()?;
+            assert_eq!(set.tag(), Tag::Set);
+            let mut set = SliceReader::new(set.value())?;
+            let (oid, value) =
+                set.sequence(|r| Ok((r.decode::<ObjectIdentifier>()?, r.decode::<Any>()?)))?;
+            let value = match value.tag() {
+                Tag::Utf8String => value.decode_as::<Utf8StringRef<'_>>()?.as_str().to_owned(),
+                Tag::PrintableString => value
+                    .decode_as::<PrintableStringRef<'_>>()?
+                    .as_str()
+                    .to_owned(),
+                tag => panic!("Unexpected {tag} in name")
+            };
+            attributes.push((oid, value));
+        }
+        Ok(attributes)
+    }
+
+    /// Decodes extensions of `tbsCertificate`.
+    fn extensions(extensions: &Any) -> der::Result<Vec<(ObjectIdentifier, bool, Vec<u8>)>> {
+        let mut reader = SliceReader::new(extensions.value())?;
+        let mut decoded = Vec::new();
+        while !reader.is_finished() {
+            decoded.push(reader.sequence(|r| {
+                Ok((
+                    r.decode::<ObjectIdentifier>()?,
+                    r.decode::<Option<bool>>()?.unwrap_or_default(),
+                    r.decode::<OctetString>()?.into_bytes()
+                ))
+            })?);
+        }
+        Ok(decoded)
+    }
+
+    fn decode(certificate: &[u8]) -> der::Result<Decoded> {
+        let mut reader = SliceReader::new(certificate)?;
+        let (tbs, algorithm, signature) = reader.sequence(|r| {
+            Ok((
+                r.decode::<Any>()?,
+                r.decode::<Any>()?,
+                r.decode::<BitString>()?
+            ))
+        })?;
+        reader.finish(())?;
+
+        let mut reader = SliceReader::new(tbs.value())?;
+        let version = ContextSpecific::<u8>::decode_explicit(&mut reader, TagNumber::N0)?
+            .expect("version is present")
+            .value;
+        let serial = reader.decode::<Uint>()?;
+        let tbs_algorithm = reader.decode::<Any>()?;
+        let issuer = reader.decode::<Any>()?;
+        let (not_before, not_after) =
+            reader.sequence(|r| Ok((r.decode::<UtcTime>()?, r.decode::<UtcTime>()?)))?;
+        let subject = reader.decode::<Any>()?;
+        let public_key = reader.decode::<Any>()?;
+        let extensions = ContextSpecific::<Any>::decode_explicit(&mut reader, TagNumber::N3)?
+            .map(|extensions| self::extensions(&extensions.value))
+            .transpose()?
+            .unwrap_or_default();
+        reader.finish(())?;
+
+        Ok(Decoded {
+            tbs: tbs.to_der()?,
+            version,
+            serial,
+            algorithm,
+            tbs_algorithm,
+            issuer: attributes(&issuer)?,
+            not_before,
+            not_after,
+            subject: attributes(&subject)?,
+            public_key: public_key.to_der()?,
+            extensions,
+            signature
+        })
+    }
+
+    fn key() -> RsaPrivateKey { RsaPrivateKey::new(&mut OsRng, TEST_BITS).unwrap() }
+
+    #[test]
+    fn certificate_fields() {
+        let key = key();
+        let before = SystemTime::now() - Duration::from_secs(1);
+        let decoded = decode(&certificate(&key, description("KEK")).unwrap()).unwrap();
+
+        assert_eq!(decoded.version, 2);
+        let serial = decoded.serial.as_bytes();
+        assert!(!serial.is_empty() && serial.len() <= 16);
+        assert_eq!(decoded.algorithm, decoded.tbs_algorithm);
+        assert_eq!(
+            decoded.algorithm.decode_as::<Any>().unwrap(),
+            decoded.algorithm
+        );
+        let expected = vec![
+            (COUNTRY, "WW".to_owned()),
+            (COMMON_NAME, "Key Exchange Key".to_owned()),
+        ];
+        assert_eq!(decoded.subject, expected);
+        assert_eq!(decoded.issuer, expected);
+
+        let not_before = decoded.not_before.to_system_time();
+        let not_after = decoded.not_after.to_system_time();
+        assert!(before <= not_before && not_before <= SystemTime::now());
+        let lifetime = not_after.duration_since(not_before).unwrap();
+        assert!(lifetime.abs_diff(VALIDITY) <= Duration::from_secs(1));
+
+        let key_usage = BitString::new(7, [DIGITAL_SIGNATURE])
+            .unwrap()
+            .to_der()
+            .unwrap();
+        assert_eq!(decoded.extensions, [(KEY_USAGE, true, key_usage)]);
+
+        assert_eq!(
+            RsaPublicKey::from_public_key_der(&decoded.public_key).unwrap(),
+            key.to_public_key()
+        );
+    }
+
+    #[test]
+    fn certificate_is_signed_by_its_key() {
+        let key = key();
+        let decoded = decode(&certificate(&key, description("PK")).unwrap()).unwrap();
+
+        let mut reader = SliceReader::new(decoded.algorithm.value()).unwrap();
+        assert_eq!(
+            reader.decode::<ObjectIdentifier>().unwrap(),
+            SHA256_WITH_RSA
+        );
+        reader.decode::<Null>().unwrap();
+
+        let verifying = VerifyingKey::<Sha256>::new(
+            RsaPublicKey::from_public_key_der(&decoded.public_key).unwrap()
+        );
+        let signature = Signature::try_from(decoded.signature.raw_bytes()).unwrap();
+        verifying.verify(&decoded.tbs, &signature).unwrap();
+
+        let mut tampered = decoded.tbs.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(verifying.verify(&tampered, &signature).is_err());
+    }
+}
@@ -0,0 +1,34 @@
+//! ## Signals
+//! This module provides functionality to gracefully handle
+//! program termination by wiping secret workspaces when the
+//! user presses Ctrl+C, or the process receives SIGTERM or
+//! SIGHUP.
+
+use color_eyre::Result;
+use color_eyre::eyre::Context as _;
+
+use crate::workspace;
+
+/// Exit code after a signal, as shells report SIGINT.
+/// Handler does not tell which signal arrived, so the same
+/// code is used for every one.
+pub const EXIT_CODE: i32 = 130;
+
+/// Initializes the SIGINT, SIGTERM and SIGHUP handler to
+/// wipe every
+/// [`SecretWorkspace`](workspace::SecretWorkspace)
+/// and exit with [`EXIT_CODE`].
+#[inline]
+pub fn init() -> Result<()> {
+    ctrlc::set_handler(|| {
+        println!();
+        tracing::info!("Interrupted, exiting...");
+
+        workspace::wipe_all();
+        std::process::exit(EXIT_CODE);
+    })
+    .context("Failed to set signal handler")?;
+
+    tracing::info!("Signal handler initialised");
+    Ok(())
+}
@@ -0,0 +1,267 @@
+//! ## SOPS
+//! Decryption and encryption of SOPS documents with
+//! `rops`.
+//!
+//! Secrets are JSON SOPS documents with `age` recipients.
+//! Binary secrets are stored by SOPS as a single `data`
+//! string, which is written out as is.
+
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::{
+    env,
+    fs
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::Context as _;
+use colored::Colorize as _;
+use rops::cryptography::cipher::AES256GCM;
+use rops::cryptography::hasher::SHA512;
+use rops::file::RopsFile;
+use rops::file::builder::RopsFileBuilder;
+use rops::file::format::JsonFileFormat;
+use rops::file::state::{
+    DecryptedFile,
+    EncryptedFile
+};
+use rops::integration::{
+    AgeIntegration,
+    Integration as _
+};
+use serde::Serialize as _;
+use serde_json::ser::{
+    PrettyFormatter,
+    Serializer
+};
+use serde_json::{
+    Map,
+    Value,
+    json
+};
+
+use crate::keys::{
+    self,
+    MasterKey
+};
+use crate::secret;
+
+/// Encrypted SOPS document, as stored in `secrets/`.
+pub type Encrypted = RopsFile<EncryptedFile<AES256GCM, SHA512>, JsonFileFormat>;
+
+/// Decrypted SOPS document.
+pub type Decrypted = RopsFile<DecryptedFile<SHA512>, JsonFileFormat>;
+
+/// Extension of SOPS documents, stripped on decryption.
+pub const EXTENSION: &str = "age";
+
+/// Metadata written by SOPS, but not by `rops`. Kept so
+/// documents stay identical in shape to the SOPS ones.
+const SOPS_METADATA: [(&str, &str); 2] = [
+    ("unencrypted_suffix", "_unencrypted"),
+    ("version", "3.10.2")
+];
+
+/// Makes `age` identities available to `rops` while alive.
+///
+/// `rops` looks up private keys only in the environment,
+/// so identities are exported there and removed on drop.
+/// Keep it alive for a single `rops` call, see [`decrypt`].
+struct Identities(());
+
+impl Identities {
+    /// Exports `identities`.
+    fn export(identities: &[String]) -> Self {
+        // SAFETY: other threads of bootstrap, the signal
+        // handler and output readers of `command::stream`,
+        // touch the environment only through `std::env`, e.g.
+        // `TZ` read by `chrono` for log timestamps. It is
+        // synchronized with `set_var` by `std`, and nothing
+        // calls `getenv` of libc directly meanwhile.
+        unsafe {
+            env::set_var(
+                AgeIntegration::private_key_env_var_name(),
+                identities.join(",")
+            );
+        }
+        Self(())
+    }
+}
+
+impl Drop for Identities {
+    fn drop(&mut self) {
+        // SAFETY: see `Identities::export`.
+        unsafe { env::remove_var(AgeIntegration::private_key_env_var_name()) };
+    }
+}
+
+/// Returns `true` if `content` looks like a SOPS document.
+pub fn is_document(content: &str) -> bool {
+    serde_json::from_str::<Value>(content).is_ok_and(|value| value.get("sops").is_some())
+}
+
+/// Decrypts document with identities of `key`.
+///
+/// Identities are in the environment only while `rops`
+/// decrypts.
+pub fn decrypt(
+    content: &str,
+    key: &MasterKey
+) -> Result<Decrypted> {
+    let encrypted = content
+        .parse::<Encrypted>()
+        .context("Failed to parse SOPS document")?;
+    let identities = key.identities()?;
+
+    let _identities = Identities::export(&identities);
+    encrypted
+        .decrypt::<JsonFileFormat>()
+        .context("Failed to decrypt SOPS document")
+}
+
+/// Returns `age` recipients of document, sorted.
+pub fn recipients(content: &str) -> Result<Vec<String>> {
+    let document: Value = serde_json::from_str(content).context("Failed to parse SOPS document")?;
+    let mut recipients = document["sops"]["age"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|unit| unit["recipient"].as_str())
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    recipients.sort();
+    Ok(recipients)
+}
+
+/// Encrypts `map` for `recipients` with a fresh data key.
+///
+/// Returns document formatted the way SOPS does, with tab
+/// indentation.
+pub fn encrypt(
+    map: Map<String, Value>,
+    recipients: &[String]
+) -> Result<String> {
+    let mut builder = RopsFileBuilder::<JsonFileFormat>::from_map(map);
+    for recipient in recipients {
+        let key_id = AgeIntegration::parse_key_id(recipient)
+            .with_context(|| format!("Invalid age recipient {recipient}"))?;
+        builder = builder.add_integration_key::<AgeIntegration>(key_id);
+    }
+    let encrypted: Encrypted = builder
+        .encrypt()
+        .context("Failed to encrypt SOPS document")?;
+
+    let mut document =
+        serde_json::to_value(&encrypted).context("Failed to serialize SOPS document")?;
+    if let Some(metadata) = document["sops"].as_object_mut() {
+        for (key, value) in SOPS_METADATA {
+            metadata.entry(key).or_insert_with(|| json!(value));
+        }
+    }
+
+    let mut content = Vec::new();
+    document
+        .serialize(&mut Serializer::with_formatter(
+            &mut content,
+            PrettyFormatter::with_indent(b"\t")
+        ))
+        .context("Failed to serialize SOPS document")?;
+    content.push(b'\n');
+    String::from_utf8(content).context("SOPS document is not UTF-8")
+}
+
+/// Wraps binary `content` the way SOPS stores binary
+/// files, under a single `data` key.
+pub fn binary(content: String) -> Map<String, Value> {
+    let mut map = Map::new();
+    map.insert("data".into(), Value::String(content));
+    map
+}
+
+/// Returns plaintext of decrypted document.
+///
+/// Binary documents are unwrapped from their `data` key,
+/// everything else is written back as JSON.
+pub fn plaintext(document: Decrypted) -> Result<Vec<u8>> {
+    let map = document.into_inner_map();
+    if map.len() == 1
+        && let Some(Value::String(data)) = map.get("data")
+    {
+        return Ok(data.clone().into_bytes());
+    }
+    serde_json::to_vec_pretty(&map).context("Failed to serialize SOPS document")
+}
+
+/// Recursively collects files of `dir`.
+pub fn walk(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut queue = vec![dir.to_path_buf()];
+    while let Some(dir) = queue.pop() {
+        for entry in
+            fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path.is_dir() {
+                queue.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Decrypts every SOPS document in the directory of `key`
+/// owner into `output`, keeping the relative layout.
+///
+/// Returns paths of decrypted files.
+pub fn decrypt_dir(
+    secrets: &Path,
+    output: &Path,
+    key: &MasterKey
+) -> Result<Vec<PathBuf>> {
+    let span = tracing::info_span!("decrypt", owner = %key.owner);
+    let _guard = span.enter();
+
+    let source = secrets.join(key.owner.dir());
+
+    let mut decrypted = Vec::new();
+    for path in walk(&source)? {
+        if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(keys::is_master_key)
+        {
+            continue;
+        }
+        let relative = path.strip_prefix(secrets)?;
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if !is_document(&content) {
+            tracing::warn!("Skipping {}: not a SOPS document", relative.display());
+            continue;
+        }
+
+        let plain = decrypt(&content, key)
+            .and_then(plaintext)
+            .with_context(|| format!("Failed to decrypt {}", relative.display()))?;
+
+        let target = if path.extension().is_some_and(|ext| ext == EXTENSION) {
+            output.join(relative.with_extension(""))
+        } else {
+            output.join(relative)
+        };
+        secret::write(&target, &plain)?;
+        tracing::info!("{} {}", "Decrypted:".blue().bold(), relative.display());
+        decrypted.push(target);
+    }
+
+    if decrypted.is_empty() {
+        tracing::warn!("No SOPS documents found in {}", source.display());
+    }
+    Ok(decrypted)
+}
@@ -0,0 +1,166 @@
+//! ## Hardware stage
+//! Checks that the machine matches the host configuration
+//! before anything is written to disks.
+//!
+//! Mismatches which would break the installation stop it,
+//! the rest are only reported. Declared disks missing on
+//! the machine can be mapped to the found ones, which is
+//! passed to `disko` and saved to the plan.
+
+use std::process::Command;
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use colored::Colorize as _;
+
+use super::{
+    Context,
+    Stage
+};
+use crate::hardware::{
+    self,
+    Declared,
+    Hardware,
+    Severity
+};
+use crate::{
+    command,
+    input,
+    secret
+};
+
+/// Extracts [`Declared`] values from the configuration.
+const DECLARED: &str = r#"config: {
+  disks = builtins.mapAttrs (_: disk: disk.device) (config.disko.devices.disk or { });
+  uefi = config.boot.loader.systemd-boot.enable
+    || (config.boot.lanzaboote.enable or false)
+    || (config.boot.loader.grub.enable && config.boot.loader.grub.efiSupport);
+  lanzaboote = config.boot.lanzaboote.enable or false;
+  cpu =
+    if config.hardware.cpu.intel.updateMicrocode then "intel"
+    else if config.hardware.cpu.amd.updateMicrocode then "amd"
+    else null;
+}"#;
+
+/// Prefix of declared disk devices, see `mkDisk`.
+pub const BY_ID: &str = "/dev/disk/by-id/";
+
+/// Compares the machine with the host configuration.
+pub struct HardwareCheck;
+
+/// Asks which found disk replaces every missing one of
+/// `declared`, recording answers in the context.
+///
+/// Returns `false` if nothing was asked.
+fn map_disks(
+    ctx: &mut Context,
+    hardware: &Hardware,
+    declared: &mut Declared
+) -> Result<bool> {
+    let missing = declared
+        .disks
+        .iter()
+        .filter(|(_, device)| !hardware::present(&ctx.sysroot, device))
+        .map(|(name, device)| (name.clone(), device.clone()))
+        .collect::<Vec<_>>();
+    if missing.is_empty() || !input::interactive() || hardware.disks.is_empty() {
+        return Ok(false);
+    }
+
+    tracing::warn!(
+        "{}",
+        "Some declared disks are missing on this machine".yellow()
+    );
+    for (name, device) in missing {
+        let used = declared.disks.values().cloned().collect::<Vec<_>>();
+        let candidates = hardware
+            .disks
+            .iter()
+            .filter(|disk| !used.contains(&format!("{BY_ID}{}", disk.id)))
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            break;
+        }
+
+        let index = dialoguer::Select::new()
+            .with_prompt(format!(
+                "Disk for {} (declared {})",
+                name.blue().bold(),
+                device.underline()
+            ))
+            .items(candidates.iter().map(|disk| disk.to_string()))
+            .default(0)
+            .interact()
+            .context("Failed to recieve input")?;
+        let id = candidates[index].id.clone();
+        tracing::info!("{} {name} -> {BY_ID}{id}", "Mapped:".blue().bold());
+        declared.disks.insert(name.clone(), format!("{BY_ID}{id}"));
+        ctx.disks.insert(name, id);
+    }
+    Ok(true)
+}
+
+impl Stage for HardwareCheck {
+    fn name(&self) -> &'static str { "hardware" }
+
+    fn run(
+        &self,
+        ctx: &mut Context
+    ) -> Result<()> {
+        let hardware = Hardware::detect(&ctx.sysroot)?;
+        tracing::info!(
+            "Firmware: {}, CPU: {}, disks: {}",
+            hardware.firmware.to_string().bold(),
+            hardware
+                .cpu
+                .map_or_else(|| "unknown".to_owned(), |cpu| cpu.to_string())
+                .bold(),
+            hardware.disks.len().to_string().bold()
+        );
+
+        let json = command::output(Command::new("nix").args([
+            "eval",
+            "--json",
+            "--apply",
+            DECLARED,
+            &ctx.attr("config")
+        ]))?;
+        let mut declared: Declared =
+            serde_json::from_str(&json).context("Failed to parse hardware of the host")?;
+
+        for (name, id) in &ctx.disks {
+            match declared.disks.get_mut(name) {
+                Some(device) => *device = format!("{BY_ID}{id}"),
+                None => tracing::warn!("Host {} declares no disk {name}", ctx.host)
+            }
+        }
+        if map_disks(ctx, &hardware, &mut declared)? {
+            if ctx.dry_run {
+                secret::dry_run(&ctx.plan_path());
+            } else {
+                ctx.save_plan()?;
+            }
+        }
+
+        let mut errors = 0;
+        for finding in hardware.check(&ctx.sysroot, &declared) {
+            match finding.severity {
+                Severity::Warning => tracing::warn!("{}", finding.message.yellow()),
+                Severity::Error => {
+                    tracing::error!("{}", finding.message.red());
+                    errors += 1;
+                }
+            }
+        }
+        if errors > 0 {
+            bail!(
+                "Machine does not match configuration of {}, {errors} problems found",
+                ctx.host
+            );
+        }
+        Ok(())
+    }
+}
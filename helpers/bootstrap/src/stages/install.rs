@@ -0,0 +1,46 @@
+//! ## Install stage
+//! Installs NixOS configuration of the host onto the
+//! mounted target root with `nixos-install`.
+//!
+//! Output is streamed through the logger under the
+//! `install` span.
+
+use std::process::Command;
+
+use color_eyre::Result;
+
+use super::{
+    Context,
+    Stage
+};
+use crate::command;
+
+/// Installs NixOS.
+pub struct Install;
+
+impl Stage for Install {
+    fn name(&self) -> &'static str { "install" }
+
+    fn run(
+        &self,
+        ctx: &mut Context
+    ) -> Result<()> {
+        let span = tracing::info_span!("install");
+        let _guard = span.enter();
+
+        // Root password prompt would be hidden by the piped
+        // output, passwords come from the configuration.
+        let mut install = Command::new("nixos-install");
+        install
+            .arg("--root")
+            .arg(&ctx.root)
+            .arg("--flake")
+            .arg(format!("{}#{}", ctx.flake.display(), ctx.host))
+            .arg("--no-root-passwd");
+        if ctx.dry_run {
+            command::dry_run(&install);
+            return Ok(());
+        }
+        command::stream(&mut install)
+    }
+}
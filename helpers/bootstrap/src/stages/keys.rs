@@ -0,0 +1,58 @@
+//! ## Keys stage
+//! Decrypts master keys of the host and users.
+
+use color_eyre::Result;
+use colored::Colorize as _;
+
+use super::{
+    Context,
+    Stage
+};
+use crate::keys::{
+    self,
+    Owner
+};
+
+/// Decrypts master keys into the temporary directory.
+pub struct Keys;
+
+impl Stage for Keys {
+    fn name(&self) -> &'static str { "keys" }
+
+    fn volatile(&self) -> bool { true }
+
+    fn run(
+        &self,
+        ctx: &mut Context
+    ) -> Result<()> {
+        let unlockers = keys::unlockers(ctx.identity.clone());
+        let host_key = keys::decrypt(
+            &ctx.secrets,
+            &ctx.output,
+            Owner::Host(ctx.host.clone()),
+            &unlockers
+        )?;
+        let mut user_keys = Vec::new();
+        for user in &ctx.users {
+            user_keys.push(keys::decrypt(
+                &ctx.secrets,
+                &ctx.output,
+                Owner::User(user.clone()),
+                &unlockers
+            )?);
+        }
+
+        for key in std::iter::once(&host_key).chain(&user_keys) {
+            tracing::info!(
+                "{} {} -> {}",
+                "Decrypted:".blue().bold(),
+                key.owner,
+                key.path.display()
+            );
+        }
+
+        ctx.host_key = Some(host_key);
+        ctx.user_keys = user_keys;
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+//! ## LUKS keys
+//! Provides key files of encrypted partitions to `disko`.
+//!
+//! Key of every partition with `isEncrypted` is stored in
+//! `secrets/hosts/<host>/luks/<partition>.age`, which the
+//! secrets stage has already decrypted. Missing keys are
+//! generated and encrypted for the host master key first.
+//! Keys are written into the secret workspace as
+//! `host.luksKeys.<partition>.txt`, the name expected by
+//! `mkPartition`.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use color_eyre::Result;
+use color_eyre::eyre::Context as _;
+use colored::Colorize as _;
+use rand::Rng as _;
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+
+use super::Context;
+use crate::keys::Owner;
+use crate::{
+    add,
+    command,
+    secret,
+    sops
+};
+
+/// Folder with LUKS keys, relative to the host secrets and
+/// to the secret workspace.
+pub const LUKS_DIR: &str = "luks";
+
+/// Length of generated keys.
+const KEY_LENGTH: usize = 64;
+
+/// Lists names of encrypted partitions of the host.
+const PARTITIONS: &str = r#"disks: builtins.concatMap (disk:
+  let partitions = disk.content.partitions or { }; in
+  builtins.filter (name: (partitions.${name}.content.type or null) == "luks")
+    (builtins.attrNames partitions)
+) (builtins.attrValues disks)"#;
+
+/// Returns name of the key file of `partition`.
+fn key_file(partition: &str) -> String { format!("host.luksKeys.{partition}.txt") }
+
+/// Returns folder passed as `luksKeysDir`.
+pub fn keys_dir(ctx: &Context) -> PathBuf { ctx.output.join(LUKS_DIR) }
+
+/// Writes keys of every encrypted partition of the host
+/// into [`keys_dir`].
+///
+/// Called once wiping is confirmed, so aborted runs do not
+/// add keys to the secrets. On dry run only prints what
+/// would be written.
+pub fn provide(ctx: &Context) -> Result<()> {
+    let span = tracing::info_span!("luks");
+    let _guard = span.enter();
+
+    let json = command::output(Command::new("nix").args([
+        "eval",
+        "--json",
+        "--apply",
+        PARTITIONS,
+        &ctx.attr("config.disko.devices.disk")
+    ]))?;
+    let partitions: Vec<String> =
+        serde_json::from_str(&json).context("Failed to parse encrypted partitions")?;
+
+    let owner = Owner::Host(ctx.host.clone());
+    let output = keys_dir(ctx);
+    for partition in partitions {
+        let dir = owner.dir().join(LUKS_DIR);
+        let relative = dir.join(format!("{partition}.{}", sops::EXTENSION));
+        let decrypted = ctx.output.join(dir).join(&partition);
+        let target = output.join(key_file(&partition));
+
+        let key = if decrypted.exists() {
+            fs::read(&decrypted)
+                .with_context(|| format!("Failed to read {}", decrypted.display()))?
+        } else {
+            let key = OsRng
+                .sample_iter(&Alphanumeric)
+                .take(KEY_LENGTH)
+                .map(char::from)
+                .collect::<String>();
+            let path = ctx.secrets.join(&relative);
+            if ctx.dry_run {
+                secret::dry_run(&path);
+            } else {
+                add::write(&path, key.clone(), &ctx.host_key()?.recipients()?, false)?;
+                tracing::info!("{} {}", "Generated:".green().bold(), relative.display());
+            }
+            key.into_bytes()
+        };
+
+        if ctx.dry_run {
+            secret::dry_run(&target);
+            continue;
+        }
+        secret::write(&target, &key)?;
+        tracing::info!("{} key of {partition}", "Provided:".blue().bold());
+    }
+    Ok(())
+}
@@ -0,0 +1,283 @@
+//! ## Stages
+//! Installation is split into stages, which are run in
+//! order by the [`Runner`].
+//!
+//! Completed stages are recorded in the [`State`], so a
+//! failed installation can be resumed without repeating
+//! work. Stages that only prepare temporary data are
+//! [`volatile`](Stage::volatile) and always run again.
+
+mod hardware;
+mod install;
+mod keys;
+mod luks;
+mod partition;
+mod postinstall;
+mod secrets;
+pub mod secureboot;
+
+use std::collections::BTreeMap;
+use std::path::{
+    Path,
+    PathBuf
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    bail,
+    eyre
+};
+use colored::Colorize as _;
+
+use crate::LOG_FILE;
+use crate::keys::MasterKey;
+use crate::plan::Plan;
+use crate::state::State;
+
+/// Folder of the installation log, relative to the target
+/// root.
+pub const LOG_DIR: &str = "var/log";
+
+/// Data shared between stages.
+pub struct Context {
+    /// Root of the flake
+    pub flake:     PathBuf,
+    /// `secrets/` folder of the flake
+    pub secrets:   PathBuf,
+    /// Temporary directory for decrypted secrets
+    pub output:    PathBuf,
+    /// Mountpoint of the target root
+    pub root:      PathBuf,
+    /// Root of the machine to inspect hardware under
+    pub sysroot:   PathBuf,
+    /// Host to install
+    pub host:      String,
+    /// Users to set up
+    pub users:     Vec<String>,
+    /// Skip confirmations
+    pub yes:       bool,
+    /// Declared disk name to device in `/dev/disk/by-id`
+    /// chosen instead of the configured one
+    pub disks:     BTreeMap<String, String>,
+    /// Plan file given with `--plan`, if any
+    pub plan:      Option<PathBuf>,
+    /// Only print what would be done
+    pub dry_run:   bool,
+    /// `age` identities unlocking token-encrypted master
+    /// keys, instead of the FIDO2 plugin
+    pub identity:  Option<PathBuf>,
+    /// Decrypted master key of the host
+    pub host_key:  Option<MasterKey>,
+    /// Decrypted master keys of the users
+    pub user_keys: Vec<MasterKey>
+}
+
+impl Context {
+    /// Returns flake reference to `path` inside the NixOS
+    /// configuration of the host.
+    pub fn attr(
+        &self,
+        path: &str
+    ) -> String {
+        format!(
+            "{}#nixosConfigurations.{}.{path}",
+            self.flake.display(),
+            self.host
+        )
+    }
+
+    /// Returns where answers are saved for a re-run: the
+    /// plan given with `--plan`, or a new one.
+    pub fn plan_path(&self) -> PathBuf { self.plan.clone().unwrap_or_else(Plan::default_path) }
+
+    /// Saves answers of this installation for a re-run with
+    /// `--plan`.
+    ///
+    /// Plan given by the operator only gets its `[disks]`
+    /// table updated, the rest of it is kept as written.
+    pub fn save_plan(&self) -> Result<()> {
+        let path = self.plan_path();
+        if self.plan.is_some() {
+            Plan::save_disks(&path, &self.disks)?;
+        } else {
+            Plan {
+                host:  Some(self.host.clone()),
+                users: self.users.clone(),
+                flake: Some(self.flake.clone()),
+                yes:   false,
+                disks: self.disks.clone()
+            }
+            .save(&path)?;
+        }
+        tracing::info!(
+            "Answers saved, re-run with {}",
+            format!("--plan {}", path.display()).bold()
+        );
+        Ok(())
+    }
+
+    /// Returns decrypted master key of the host.
+    pub fn host_key(&self) -> Result<&MasterKey> {
+        self.host_key
+            .as_ref()
+            .ok_or_else(|| eyre!("Master key of host {} is not decrypted", self.host))
+    }
+}
+
+/// Single step of the installation.
+pub trait Stage {
+    /// Name of the stage, used in the state file and for
+    /// `--from-stage`.
+    fn name(&self) -> &'static str;
+
+    /// Whether stage has to run again on resume, because
+    /// its results do not outlive the process.
+    fn volatile(&self) -> bool { false }
+
+    /// Whether the target root is mounted once the stage
+    /// has completed, so the log can be moved onto it.
+    fn mounts_root(&self) -> bool { false }
+
+    /// Runs the stage.
+    fn run(
+        &self,
+        ctx: &mut Context
+    ) -> Result<()>;
+}
+
+/// Where to start the installation from.
+pub enum Start {
+    /// Run every stage
+    Beginning,
+    /// Continue after the last completed stage
+    Resume,
+    /// Start from the named stage
+    From(String)
+}
+
+/// Moves the log file onto the target root, so it
+/// survives the live system.
+fn keep_log(root: &Path) {
+    let path = root.join(LOG_DIR).join(LOG_FILE);
+    match niac_log::relocate(&path) {
+        Ok(()) => tracing::info!("{} {}", "Log:".blue().bold(), path.display()),
+        Err(error) => tracing::warn!("Failed to move log to {}: {error:#}", path.display())
+    }
+}
+
+/// Runs stages in order, recording progress.
+pub struct Runner {
+    stages: Vec<Box<dyn Stage>>
+}
+
+impl Runner {
+    /// Creates runner with every stage of the installation.
+    pub fn new() -> Self {
+        Self {
+            stages: vec![
+                Box::new(keys::Keys),
+                Box::new(secrets::Secrets),
+                Box::new(hardware::HardwareCheck),
+                Box::new(partition::Partition),
+                Box::new(secureboot::SecureBoot),
+                Box::new(install::Install),
+                Box::new(postinstall::PostInstall),
+            ]
+        }
+    }
+
+    /// Returns index of the first stage to run.
+    fn start(
+        &self,
+        start: &Start,
+        state: &State
+    ) -> Result<usize> {
+        match start {
+            Start::Beginning => Ok(0),
+            Start::Resume => Ok(self
+                .stages
+                .iter()
+                .position(|stage| !state.is_completed(stage.name()))
+                .unwrap_or(self.stages.len())),
+            Start::From(name) => self
+                .stages
+                .iter()
+                .position(|stage| stage.name() == name)
+                .ok_or_else(|| {
+                    eyre!(
+                        "Unknown stage {}, expected one of: {}",
+                        name.red(),
+                        self.stages
+                            .iter()
+                            .map(|stage| stage.name())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })
+        }
+    }
+
+    /// Runs stages, starting from `start`.
+    ///
+    /// Stages before the start are skipped, except volatile
+    /// ones. State is saved after every stage and removed
+    /// once the installation is finished, unless it is a
+    /// dry run.
+    pub fn run(
+        &self,
+        ctx: &mut Context,
+        state: &mut State,
+        start: &Start
+    ) -> Result<()> {
+        let first = self.start(start, state)?;
+        if first == self.stages.len() {
+            bail!(
+                "Every stage has already completed, remove {} to start over",
+                state.path().display()
+            );
+        }
+        let kept = self.stages[..first]
+            .iter()
+            .map(|stage| stage.name())
+            .collect::<Vec<_>>();
+        state.completed.retain(|name| kept.contains(&name.as_str()));
+
+        for (index, stage) in self.stages.iter().enumerate() {
+            let span = tracing::info_span!("stage", name = stage.name());
+            let _guard = span.enter();
+
+            if index < first && !stage.volatile() {
+                tracing::info!("{} {}", "Skipping:".yellow().bold(), stage.name());
+                if stage.mounts_root() && !ctx.dry_run {
+                    keep_log(&ctx.root);
+                }
+                continue;
+            }
+
+            tracing::info!("{} {}", "Running:".blue().bold(), stage.name());
+            stage.run(ctx)?;
+
+            if ctx.dry_run {
+                continue;
+            }
+            if stage.mounts_root() {
+                keep_log(&ctx.root);
+            }
+            if !state.is_completed(stage.name()) {
+                state.completed.push(stage.name().into());
+            }
+            state.disks.clone_from(&ctx.disks);
+            state.save()?;
+        }
+
+        if ctx.dry_run {
+            tracing::info!(
+                "{}",
+                "Dry run completed, nothing was changed".green().bold()
+            );
+            return Ok(());
+        }
+        tracing::info!("{}", "All stages completed".green().bold());
+        state.remove()
+    }
+}
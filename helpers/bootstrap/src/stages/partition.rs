@@ -0,0 +1,172 @@
+//! ## Partition stage
+//! Wipes, partitions and mounts disks of the host with
+//! `disko`.
+//!
+//! Disko script is built from the host configuration with
+//! `luksKeysDir` pointed at the secret workspace, so LUKS
+//! keys never leave it.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use colored::Colorize as _;
+
+use super::{
+    Context,
+    Stage,
+    luks
+};
+use crate::{
+    command,
+    hardware,
+    input
+};
+
+/// Environment variable read by `helpers/default.nix`.
+pub const LUKS_KEYS_DIR: &str = "NIaC_LUKS_KEYS_DIR";
+
+/// Environment variable with disks chosen instead of the
+/// declared ones, as JSON. Read by `helpers/default.nix`.
+pub const DISKS: &str = "NIaC_DISKS";
+
+/// Partitions disks of the host.
+pub struct Partition;
+
+/// Returns `nix` command seeing disks chosen in the
+/// [`Context`] and LUKS keys in its workspace.
+fn nix(ctx: &Context) -> Result<Command> {
+    let mut nix = Command::new("nix");
+    nix.env(LUKS_KEYS_DIR, luks::keys_dir(ctx)).env(
+        DISKS,
+        serde_json::to_string(&ctx.disks).context("Failed to serialize disks")?
+    );
+    Ok(nix)
+}
+
+/// Evaluates disks of the host, as
+/// `name -> /dev/disk/by-id/<device>`.
+pub fn disks(ctx: &Context) -> Result<BTreeMap<String, String>> {
+    let json = command::output(nix(ctx)?.args([
+        "eval",
+        "--impure",
+        "--json",
+        "--apply",
+        "builtins.mapAttrs (_: disk: disk.device)",
+        &ctx.attr("config.disko.devices.disk")
+    ]))?;
+    serde_json::from_str(&json).context("Failed to parse disks of the host")
+}
+
+/// Shows which devices of the machine whose `/` is at
+/// `root` are going to be wiped.
+///
+/// Returns names of disks missing on this machine.
+fn summary(
+    root: &Path,
+    disks: &BTreeMap<String, String>
+) -> Vec<String> {
+    tracing::warn!(
+        "{}",
+        "Following disks will be WIPED, all data on them will be lost:"
+            .red()
+            .bold()
+    );
+
+    let mut missing = Vec::new();
+    for (name, device) in disks {
+        match hardware::resolve(root, device) {
+            Some(real) => tracing::warn!(
+                "  {} {} -> {}",
+                name.bold(),
+                device.underline(),
+                real.display().to_string().red().bold()
+            ),
+            None => {
+                tracing::error!(
+                    "  {} {} {}",
+                    name.bold(),
+                    device.underline(),
+                    "not found!".red()
+                );
+                missing.push(name.clone());
+            }
+        }
+    }
+    missing
+}
+
+/// Asks operator to type the hostname before wiping disks.
+fn confirm(ctx: &Context) -> Result<()> {
+    if ctx.yes {
+        tracing::warn!("Wiping disks without confirmation, because of --yes");
+        return Ok(());
+    }
+    if !input::interactive() {
+        bail!("Wiping disks requires confirmation, but stdin is not a terminal");
+    }
+
+    let answer = dialoguer::Input::<'_, String>::new()
+        .with_prompt(format!(
+            "Type {} to wipe the disks",
+            ctx.host.red().bold().underline()
+        ))
+        .allow_empty(true)
+        .interact_text()
+        .context("Failed to recieve input")?;
+    if answer != ctx.host {
+        bail!("Confirmation failed, disks are left untouched");
+    }
+    Ok(())
+}
+
+impl Stage for Partition {
+    fn name(&self) -> &'static str { "partition" }
+
+    fn mounts_root(&self) -> bool { true }
+
+    fn run(
+        &self,
+        ctx: &mut Context
+    ) -> Result<()> {
+        let disks = disks(ctx)?;
+        if disks.is_empty() {
+            bail!("Host {} declares no disks", ctx.host);
+        }
+        let missing = summary(&ctx.sysroot, &disks);
+        if !missing.is_empty() {
+            bail!(
+                "Disks {} are not present on this machine",
+                missing.join(", ")
+            );
+        }
+
+        // Building only adds the script to the store, so it
+        // is done on dry run too. Key files are read by the
+        // script only when it runs.
+        tracing::info!("Building disko script...");
+        let script = command::output(nix(ctx)?.args([
+            "build",
+            "--impure",
+            "--no-link",
+            "--print-out-paths",
+            &ctx.attr("config.system.build.diskoScript")
+        ]))?;
+        let mut disko = Command::new(script.trim());
+        if ctx.dry_run {
+            luks::provide(ctx)?;
+            command::dry_run(&disko);
+            return Ok(());
+        }
+
+        confirm(ctx)?;
+        // New keys are useless if disks are left untouched.
+        luks::provide(ctx)?;
+        command::run(&mut disko)
+    }
+}
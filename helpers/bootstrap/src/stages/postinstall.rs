@@ -0,0 +1,224 @@
+//! ## Post-install stage
+//! Places decrypted master keys where `sops-nix` looks for
+//! them on the installed system.
+//!
+//! Host key becomes the system age key, user keys go to
+//! `~/.config/sops/age/keys.txt` of each user. Homes,
+//! owners and modes are taken from the evaluated
+//! configuration, numeric IDs from `/etc/passwd` and
+//! `/etc/group` of the target, which `nixos-install` has
+//! already activated.
+
+use std::fs::{
+    self,
+    Permissions
+};
+use std::os::unix::fs::{
+    PermissionsExt as _,
+    chown
+};
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::process::Command;
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    OptionExt as _,
+    bail,
+    eyre
+};
+use colored::Colorize as _;
+use serde::Deserialize;
+
+use super::{
+    Context,
+    Stage
+};
+use crate::keys::Owner;
+use crate::{
+    command,
+    secret
+};
+
+/// System key used when `sops.age.keyFile` is not set.
+pub const SYSTEM_KEY: &str = "/var/lib/sops-nix/key.txt";
+
+/// Age key of a user, relative to their home.
+pub const USER_KEY: &str = ".config/sops/age/keys.txt";
+
+/// Places master keys on the installed system.
+pub struct PostInstall;
+
+/// Account of a user, as declared in the configuration.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Account {
+    home:      PathBuf,
+    group:     String,
+    home_mode: String
+}
+
+/// Returns `path` inside the target root.
+fn target(
+    ctx: &Context,
+    path: &Path
+) -> PathBuf {
+    ctx.root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// Evaluates where `sops-nix` reads the system age key.
+fn system_key(ctx: &Context) -> Result<PathBuf> {
+    let json = command::output(Command::new("nix").args([
+        "eval",
+        "--json",
+        "--apply",
+        "config: config.sops.age.keyFile or null",
+        &ctx.attr("config")
+    ]))?;
+    let path: Option<PathBuf> =
+        serde_json::from_str(&json).context("Failed to parse sops.age.keyFile")?;
+    Ok(path.unwrap_or_else(|| SYSTEM_KEY.into()))
+}
+
+/// Evaluates account of `user`.
+fn account(
+    ctx: &Context,
+    user: &str
+) -> Result<Account> {
+    let json = command::output(Command::new("nix").args([
+        "eval",
+        "--json",
+        "--apply",
+        "user: { inherit (user) home group homeMode; }",
+        &ctx.attr(&format!("config.users.users.{user}"))
+    ]))?;
+    serde_json::from_str(&json).with_context(|| format!("Failed to parse account of {user}"))
+}
+
+/// Looks up numeric ID of `name` in a `passwd`-like
+/// database of the target.
+fn id(
+    ctx: &Context,
+    database: &str,
+    name: &str
+) -> Result<u32> {
+    let path = ctx.root.join("etc").join(database);
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let line = content
+        .lines()
+        .find(|line| line.split(':').next() == Some(name))
+        .ok_or_else(|| eyre!("{name} is not present in {}", path.display()))?;
+    line.split(':')
+        .nth(2)
+        .ok_or_eyre("Entry has no ID")?
+        .parse()
+        .with_context(|| format!("Invalid ID of {name} in {}", path.display()))
+}
+
+/// Creates `path` with `mode` if it does not exist, and
+/// gives it to `uid:gid`.
+fn dir(
+    path: &Path,
+    mode: u32,
+    uid: u32,
+    gid: u32
+) -> Result<()> {
+    if !path.exists() {
+        fs::create_dir(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        fs::set_permissions(path, Permissions::from_mode(mode))
+            .with_context(|| format!("Failed to set mode of {}", path.display()))?;
+    }
+    chown(path, Some(uid), Some(gid))
+        .with_context(|| format!("Failed to change owner of {}", path.display()))
+}
+
+/// Places key of `user` into their home.
+fn user_key(
+    ctx: &Context,
+    user: &str,
+    key: &Path
+) -> Result<PathBuf> {
+    let account = account(ctx, user)?;
+    let home = target(ctx, &account.home);
+    let path = home.join(USER_KEY);
+    if ctx.dry_run {
+        secret::dry_run(&path);
+        return Ok(path);
+    }
+
+    let uid = id(ctx, "passwd", user)?;
+    let gid = id(ctx, "group", &account.group)?;
+    let mode = u32::from_str_radix(&account.home_mode, 8)
+        .with_context(|| format!("Invalid homeMode of {user}: {}", account.home_mode))?;
+
+    if let Some(parent) = home.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    dir(&home, mode, uid, gid)?;
+
+    let Some(parent) = path.parent() else {
+        bail!("Key of {user} has no parent directory");
+    };
+    for ancestor in parent
+        .ancestors()
+        .take_while(|ancestor| *ancestor != home)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+    {
+        dir(ancestor, 0o700, uid, gid)?;
+    }
+
+    let content = fs::read(key).with_context(|| format!("Failed to read {}", key.display()))?;
+    secret::write(&path, &content)?;
+    chown(&path, Some(uid), Some(gid))
+        .with_context(|| format!("Failed to change owner of {}", path.display()))?;
+    Ok(path)
+}
+
+impl Stage for PostInstall {
+    fn name(&self) -> &'static str { "post-install" }
+
+    fn run(
+        &self,
+        ctx: &mut Context
+    ) -> Result<()> {
+        let host_key = ctx.host_key()?;
+        let path = target(ctx, &system_key(ctx)?);
+        if ctx.dry_run {
+            secret::dry_run(&path);
+        } else {
+            let content = fs::read(&host_key.path)
+                .with_context(|| format!("Failed to read {}", host_key.path.display()))?;
+            secret::write(&path, &content)?;
+            tracing::info!(
+                "{} {} -> {}",
+                "Placed:".blue().bold(),
+                host_key.owner,
+                path.display()
+            );
+        }
+
+        for key in &ctx.user_keys {
+            let Owner::User(user) = &key.owner else {
+                continue;
+            };
+            let path = user_key(ctx, user, &key.path)?;
+            if ctx.dry_run {
+                continue;
+            }
+            tracing::info!(
+                "{} {} -> {}",
+                "Placed:".blue().bold(),
+                key.owner,
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
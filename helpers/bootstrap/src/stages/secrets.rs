@@ -0,0 +1,27 @@
+//! ## Secrets stage
+//! Decrypts SOPS documents of the host.
+
+use color_eyre::Result;
+
+use super::{
+    Context,
+    Stage
+};
+use crate::sops;
+
+/// Decrypts SOPS documents into the temporary directory.
+pub struct Secrets;
+
+impl Stage for Secrets {
+    fn name(&self) -> &'static str { "secrets" }
+
+    fn volatile(&self) -> bool { true }
+
+    fn run(
+        &self,
+        ctx: &mut Context
+    ) -> Result<()> {
+        sops::decrypt_dir(&ctx.secrets, &ctx.output, ctx.host_key()?)?;
+        Ok(())
+    }
+}
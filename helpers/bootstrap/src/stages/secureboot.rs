@@ -0,0 +1,140 @@
+//! ## SecureBoot stage
+//! Places SecureBoot keys of the host into the `sbctl` PKI
+//! bundle on the target root, which is what `lanzaboote`
+//! signs with. Bundle is the `boot.lanzaboote.pkiBundle` of
+//! the host.
+//!
+//! Keys are stored in
+//! `secrets/hosts/<host>/secureBootKeys/` as `GUID.age` and
+//! `{PK,KEK,db}/{key,pem}.age`.
+
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::process::Command;
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use colored::Colorize as _;
+
+use super::{
+    Context,
+    Stage
+};
+use crate::{
+    command,
+    secret,
+    sops
+};
+
+/// Folder with SecureBoot keys, relative to the host
+/// secrets.
+pub const KEYS_DIR: &str = "secureBootKeys";
+
+/// `sbctl` PKI bundle used when `boot.lanzaboote.pkiBundle`
+/// is not set.
+pub const PKI_BUNDLE: &str = "/var/lib/sbctl";
+
+/// Keys of the SecureBoot hierarchy.
+pub const HIERARCHY: [&str; 3] = ["PK", "KEK", "db"];
+
+/// Places SecureBoot keys, if the host has them.
+pub struct SecureBoot;
+
+/// Returns `(source, target)` pairs of every key file, with
+/// source relative to the keys folder and target relative
+/// to the PKI bundle.
+pub fn layout() -> Vec<(PathBuf, PathBuf)> {
+    let mut files = vec![(PathBuf::from("GUID.age"), PathBuf::from("GUID"))];
+    for key in HIERARCHY {
+        for kind in ["key", "pem"] {
+            files.push((
+                Path::new(key).join(format!("{kind}.{}", sops::EXTENSION)),
+                Path::new("keys").join(key).join(format!("{key}.{kind}"))
+            ));
+        }
+    }
+    files
+}
+
+/// Evaluates where `lanzaboote` reads the PKI bundle.
+fn pki_bundle(ctx: &Context) -> Result<PathBuf> {
+    let json = command::output(Command::new("nix").args([
+        "eval",
+        "--json",
+        "--apply",
+        "config: config.boot.lanzaboote.pkiBundle or null",
+        &ctx.attr("config")
+    ]))?;
+    let path: Option<PathBuf> =
+        serde_json::from_str(&json).context("Failed to parse boot.lanzaboote.pkiBundle")?;
+    Ok(path.unwrap_or_else(|| PKI_BUNDLE.into()))
+}
+
+impl Stage for SecureBoot {
+    fn name(&self) -> &'static str { "secureboot" }
+
+    fn run(
+        &self,
+        ctx: &mut Context
+    ) -> Result<()> {
+        let source = ctx.secrets.join("hosts").join(&ctx.host).join(KEYS_DIR);
+        if !source.exists() {
+            tracing::info!(
+                "Host {} has no SecureBoot keys, skipping",
+                ctx.host.blue().bold()
+            );
+            return Ok(());
+        }
+
+        let layout = layout();
+        let missing = layout
+            .iter()
+            .filter(|(file, _)| !source.join(file).exists())
+            .map(|(file, _)| file.display().to_string())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            bail!(
+                "SecureBoot keys of {} are incomplete, missing: {}",
+                ctx.host,
+                missing.join(", ")
+            );
+        }
+
+        let bundle = pki_bundle(ctx)?;
+        let bundle = ctx.root.join(bundle.strip_prefix("/").unwrap_or(&bundle));
+        let key = ctx.host_key()?;
+        for (file, target) in layout {
+            let path = source.join(&file);
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let plain = sops::decrypt(&content, key)
+                .and_then(sops::plaintext)
+                .with_context(|| format!("Failed to decrypt {}", file.display()))?;
+
+            let target = bundle.join(&target);
+            if ctx.dry_run {
+                secret::dry_run(&target);
+                continue;
+            }
+            secret::write(&target, &plain)?;
+            tracing::info!(
+                "{} {} -> {}",
+                "Placed:".blue().bold(),
+                file.display(),
+                target.display()
+            );
+        }
+
+        if ctx.dry_run {
+            return Ok(());
+        }
+        tracing::info!("SecureBoot keys placed into {}", bundle.display());
+        Ok(())
+    }
+}
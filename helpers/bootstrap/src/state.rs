@@ -0,0 +1,142 @@
+//! ## State
+//! Progress of the installation, saved after every stage so
+//! a failed run can be resumed.
+
+use std::collections::BTreeMap;
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::{
+    env,
+    fs
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+/// Saved progress of the installation.
+#[derive(Default, Serialize, Deserialize)]
+pub struct State {
+    /// Host being installed
+    pub host:      Option<String>,
+    /// Users being set up
+    #[serde(default)]
+    pub users:     Vec<String>,
+    /// Names of completed stages, in order
+    #[serde(default)]
+    pub completed: Vec<String>,
+    /// Disks mapped by the operator, as in
+    /// [`Context::disks`](crate::stages::Context::disks)
+    #[serde(default)]
+    pub disks:     BTreeMap<String, String>,
+
+    /// Where state is saved
+    #[serde(skip)]
+    path: PathBuf
+}
+
+impl State {
+    /// Default location of the state file.
+    ///
+    /// Temporary directory survives a failed run on the
+    /// live ISO, but not a reboot into the installed
+    /// system.
+    pub fn default_path() -> PathBuf { env::temp_dir().join("niac-bootstrap.state.toml") }
+
+    /// Creates empty state, saved to `path`.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            ..Default::default()
+        }
+    }
+
+    /// Loads state from `path`.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read state {}", path.display()))?;
+        let state: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse state {}", path.display()))?;
+        Ok(Self { path, ..state })
+    }
+
+    /// Loads state from `path`, or creates empty one if it
+    /// does not exist.
+    pub fn load_or_new(path: PathBuf) -> Result<Self> {
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::new(path))
+        }
+    }
+
+    /// Path of the state file.
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Writes state to its file.
+    pub fn save(&self) -> Result<()> {
+        let content = toml::to_string(self).context("Failed to serialize state")?;
+        fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write state {}", self.path.display()))
+    }
+
+    /// Removes state file, if any.
+    pub fn remove(&self) -> Result<()> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)
+                .with_context(|| format!("Failed to remove state {}", self.path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Checks that `host` and `users` given to a resumed
+    /// run are the ones the state was saved for, since
+    /// completed stages were done for them.
+    ///
+    /// Missing values are taken from the state later, so
+    /// they always match.
+    pub fn check_resume(
+        &self,
+        host: Option<&str>,
+        users: &[String]
+    ) -> Result<()> {
+        let hint = format!(
+            "drop --resume to start over, or delete the state file {}",
+            self.path.display()
+        );
+        if let (Some(saved), Some(given)) = (&self.host, host)
+            && saved != given
+        {
+            bail!("State is of host {saved}, but host {given} was given; {hint}");
+        }
+
+        let mut saved = self.users.clone();
+        let mut given = users.to_vec();
+        saved.sort();
+        given.sort();
+        if !saved.is_empty() && !given.is_empty() && saved != given {
+            bail!(
+                "State is of users {}, but users {} were given; {hint}",
+                saved.join(", "),
+                given.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if stage `name` has completed.
+    pub fn is_completed(
+        &self,
+        name: &str
+    ) -> bool {
+        self.completed.iter().any(|stage| stage == name)
+    }
+}
@@ -0,0 +1,397 @@
+//! ## Verify
+//! Checks the `secrets/` tree before it is needed by an
+//! installation.
+//!
+//! Every SOPS document must parse and be encrypted for the
+//! recipients of its creation rule in `.sops.yaml`. With
+//! `--unlock`, master keys are decrypted and MACs of the
+//! documents are checked too. Every host and user must
+//! have an encrypted master key, including those which only
+//! have a configuration.
+
+use std::collections::BTreeMap;
+use std::io::Write as _;
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::{
+    fmt,
+    fs,
+    io
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::{
+    Context as _,
+    bail
+};
+use colored::Colorize as _;
+use serde::{
+    Serialize,
+    Serializer
+};
+
+use crate::init::{
+    HOSTS_DIR,
+    USERS_DIR
+};
+use crate::keys::{
+    self,
+    KeyUnlocker,
+    MasterKey,
+    Owner
+};
+use crate::rules::Rules;
+use crate::workspace::SecretWorkspace;
+use crate::{
+    cli,
+    sops
+};
+
+/// `--report` value which prints the report to stdout.
+pub const STDOUT: &str = "-";
+
+/// What was checked.
+#[derive(Clone, Copy)]
+pub enum Kind {
+    /// Owner has an encrypted master key
+    MasterKey,
+    /// Document is a valid SOPS document
+    Parse,
+    /// Document is encrypted for the expected recipients
+    Recipients,
+    /// Document decrypts and its MAC is valid
+    Mac
+}
+
+impl Kind {
+    /// Name of the check, in logs and the report.
+    fn name(self) -> &'static str {
+        match self {
+            Self::MasterKey => "master-key",
+            Self::Parse => "parse",
+            Self::Recipients => "recipients",
+            Self::Mac => "mac"
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>
+    ) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Serialize for Kind {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+/// Outcome of a check.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Passed,
+    Failed,
+    Skipped
+}
+
+/// Single check of a file or folder.
+#[derive(Serialize)]
+pub struct Check {
+    /// Path relative to the flake root
+    pub path:    PathBuf,
+    pub kind:    Kind,
+    pub status:  Status,
+    /// Why check failed or was skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>
+}
+
+/// Machine-readable result of the verification.
+#[derive(Default, Serialize)]
+pub struct Report {
+    pub passed:  usize,
+    pub failed:  usize,
+    pub skipped: usize,
+    pub checks:  Vec<Check>
+}
+
+impl Report {
+    /// Records and logs a check.
+    fn push(
+        &mut self,
+        path: &Path,
+        kind: Kind,
+        status: Status,
+        message: Option<String>
+    ) {
+        let detail = message.as_deref().unwrap_or_default();
+        match status {
+            Status::Passed => {
+                self.passed += 1;
+                tracing::debug!("{} {kind} {}", "Passed:".green().bold(), path.display());
+            },
+            Status::Failed => {
+                self.failed += 1;
+                tracing::error!(
+                    "{} {kind} {}: {detail}",
+                    "Failed:".red().bold(),
+                    path.display()
+                );
+            },
+            Status::Skipped => {
+                self.skipped += 1;
+                tracing::debug!(
+                    "{} {kind} {}: {detail}",
+                    "Skipped:".yellow().bold(),
+                    path.display()
+                );
+            }
+        }
+        self.checks.push(Check {
+            path: path.to_path_buf(),
+            kind,
+            status,
+            message
+        });
+    }
+}
+
+/// Returns names of folders in `dir`, if it exists.
+fn folders(dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    if !dir.is_dir() {
+        return Ok(names);
+    }
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    Ok(names)
+}
+
+/// Returns hosts and users which have a configuration or a
+/// folder in `secrets/`.
+///
+/// Configurations are listed too, so an owner whose
+/// secrets are missing altogether is still reported.
+fn owners(flake: &Path) -> Result<Vec<Owner>> {
+    let secrets = flake.join("secrets");
+    let mut owners = Vec::new();
+    for (kind, configs) in [("hosts", HOSTS_DIR), ("users", USERS_DIR)] {
+        let mut names = folders(&flake.join(configs))?;
+        names.extend(folders(&secrets.join(kind))?);
+        names.sort();
+        names.dedup();
+        owners.extend(names.into_iter().map(|name| match kind {
+            "hosts" => Owner::Host(name),
+            _ => Owner::User(name)
+        }));
+    }
+    owners.sort_by_key(Owner::dir);
+    Ok(owners)
+}
+
+/// Checks master key of `owner`, decrypting it into
+/// `output` if `unlockers` are given.
+fn master_key(
+    report: &mut Report,
+    secrets: &Path,
+    output: &Path,
+    owner: &Owner,
+    unlockers: Option<&[Box<dyn KeyUnlocker>]>
+) -> Option<MasterKey> {
+    let dir = Path::new("secrets").join(owner.dir());
+    if !secrets.join(owner.dir()).is_dir() {
+        report.push(
+            &dir,
+            Kind::MasterKey,
+            Status::Failed,
+            Some(format!("{owner} has no folder in secrets/"))
+        );
+        return None;
+    }
+    if !keys::MASTER_KEYS
+        .iter()
+        .any(|file| secrets.join(owner.dir()).join(file).exists())
+    {
+        report.push(
+            &dir,
+            Kind::MasterKey,
+            Status::Failed,
+            Some(format!("none of {} found", keys::MASTER_KEYS.join(", ")))
+        );
+        return None;
+    }
+    report.push(&dir, Kind::MasterKey, Status::Passed, None);
+
+    let unlockers = unlockers?;
+    match keys::decrypt(secrets, output, owner.clone(), unlockers) {
+        Ok(key) => Some(key),
+        Err(err) => {
+            tracing::warn!("Master key of {owner} is not unlocked: {err:#}");
+            None
+        }
+    }
+}
+
+/// Checks document at `path` of the flake.
+fn document(
+    report: &mut Report,
+    flake: &Path,
+    path: &Path,
+    rules: &Rules,
+    key: Option<&MasterKey>
+) -> Result<()> {
+    let relative = path.strip_prefix(flake)?;
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    if !sops::is_document(&content) {
+        if path.extension().is_some_and(|ext| ext == sops::EXTENSION) {
+            report.push(
+                relative,
+                Kind::Parse,
+                Status::Failed,
+                Some("not a SOPS document".into())
+            );
+        }
+        return Ok(());
+    }
+    if let Err(err) = content.parse::<sops::Encrypted>() {
+        report.push(
+            relative,
+            Kind::Parse,
+            Status::Failed,
+            Some(format!("{err:#}"))
+        );
+        return Ok(());
+    }
+    report.push(relative, Kind::Parse, Status::Passed, None);
+
+    let actual = sops::recipients(&content)?;
+    match rules.recipients(relative) {
+        None => report.push(
+            relative,
+            Kind::Recipients,
+            Status::Failed,
+            Some("no creation rule matches".into())
+        ),
+        Some(expected) if expected == actual => {
+            report.push(relative, Kind::Recipients, Status::Passed, None);
+        },
+        Some(expected) => {
+            let missing = expected
+                .iter()
+                .filter(|recipient| !actual.contains(recipient));
+            let unexpected = actual
+                .iter()
+                .filter(|recipient| !expected.contains(recipient));
+            let message = missing
+                .map(|recipient| format!("missing {recipient}"))
+                .chain(unexpected.map(|recipient| format!("unexpected {recipient}")))
+                .collect::<Vec<_>>()
+                .join(", ");
+            report.push(relative, Kind::Recipients, Status::Failed, Some(message));
+        }
+    }
+
+    let Some(key) = key else {
+        report.push(
+            relative,
+            Kind::Mac,
+            Status::Skipped,
+            Some("master key is not unlocked".into())
+        );
+        return Ok(());
+    };
+    match sops::decrypt(&content, key) {
+        Ok(_) => report.push(relative, Kind::Mac, Status::Passed, None),
+        Err(err) => report.push(
+            relative,
+            Kind::Mac,
+            Status::Failed,
+            Some(format!("{err:#}"))
+        )
+    }
+    Ok(())
+}
+
+/// Verifies `secrets/` of the flake at `flake`.
+pub fn run(
+    flake: &Path,
+    args: cli::Verify,
+    identity: Option<PathBuf>
+) -> Result<()> {
+    let span = tracing::info_span!("verify");
+    let _guard = span.enter();
+
+    let secrets = flake.join("secrets");
+    let rules = Rules::load(flake)?;
+    let workspace = SecretWorkspace::new("verify")?;
+    let unlockers = args.unlock.then(|| keys::unlockers(identity));
+
+    let mut report = Report::default();
+    let mut unlocked = BTreeMap::new();
+    for owner in owners(flake)? {
+        if let Some(key) = master_key(
+            &mut report,
+            &secrets,
+            workspace.path(),
+            &owner,
+            unlockers.as_deref()
+        ) {
+            unlocked.insert(owner.dir(), key);
+        }
+    }
+
+    for path in sops::walk(&secrets)? {
+        if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(keys::is_master_key)
+        {
+            continue;
+        }
+        let key = path
+            .strip_prefix(&secrets)
+            .ok()
+            .and_then(Owner::from_path)
+            .and_then(|owner| unlocked.get(&owner.dir()));
+        document(&mut report, flake, &path, &rules, key)?;
+    }
+
+    if let Some(path) = &args.report {
+        let content =
+            serde_json::to_string_pretty(&report).context("Failed to serialize report")?;
+        if path == Path::new(STDOUT) {
+            writeln!(io::stdout().lock(), "{content}").context("Failed to print report")?;
+        } else {
+            fs::write(path, content + "\n")
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            tracing::info!("{} {}", "Report:".blue().bold(), path.display());
+        }
+    }
+
+    tracing::info!(
+        "{} passed, {} failed, {} skipped",
+        report.passed.to_string().green().bold(),
+        report.failed.to_string().red().bold(),
+        report.skipped.to_string().yellow().bold()
+    );
+    if report.failed > 0 {
+        bail!("{} checks of secrets failed", report.failed);
+    }
+    Ok(())
+}
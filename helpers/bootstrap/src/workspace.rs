@@ -0,0 +1,181 @@
+//! ## Secret workspace
+//! Temporary directory for decrypted secrets, which is
+//! wiped on every exit path.
+//!
+//! Workspace is placed on a memory-backed filesystem when
+//! one is available, so secrets never reach a disk. A
+//! `memfd` would be safer still, but `disko` and
+//! `nixos-install` need real paths to read keys from.
+//!
+//! Files are overwritten with zeros before removal. Drop
+//! covers normal exit and returned errors, [`wipe_all`]
+//! covers signals and panics.
+
+use std::env;
+use std::fs::{
+    self,
+    OpenOptions,
+    Permissions
+};
+use std::io::{
+    self,
+    Write as _
+};
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::{
+    Path,
+    PathBuf
+};
+use std::sync::{
+    Mutex,
+    PoisonError
+};
+
+use color_eyre::Result;
+use color_eyre::eyre::Context as _;
+
+/// Paths of workspaces alive in this process.
+static ACTIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Filesystems which keep data in memory only.
+const MEMORY_FS: [&str; 2] = ["tmpfs", "ramfs"];
+
+/// Temporary directory wiped when dropped.
+pub struct SecretWorkspace {
+    path: PathBuf
+}
+
+/// Returns filesystem type `path` is mounted on.
+fn filesystem(path: &Path) -> Option<String> {
+    let path = fs::canonicalize(path).ok()?;
+    let mounts = fs::read_to_string("/proc/self/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            Some((Path::new(fields.next()?), fields.next()?))
+        })
+        .filter(|(mountpoint, _)| path.starts_with(mountpoint))
+        .max_by_key(|(mountpoint, _)| mountpoint.as_os_str().len())
+        .map(|(_, kind)| kind.to_owned())
+}
+
+/// Picks directory to create workspace in, preferring
+/// memory-backed ones.
+fn base() -> PathBuf {
+    let candidates = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain([PathBuf::from("/dev/shm"), env::temp_dir()]);
+    for candidate in candidates {
+        if filesystem(&candidate).is_some_and(|kind| MEMORY_FS.contains(&kind.as_str())) {
+            return candidate;
+        }
+    }
+
+    let fallback = env::temp_dir();
+    tracing::warn!(
+        "No memory-backed filesystem found, secrets are kept in {}",
+        fallback.display()
+    );
+    fallback
+}
+
+/// Overwrites every file under `path` with zeros and
+/// removes it.
+fn wipe(path: &Path) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let kind = entry.file_type()?;
+            if kind.is_dir() {
+                dirs.push(entry.path());
+            } else if kind.is_file() {
+                let mut file = OpenOptions::new().write(true).open(entry.path())?;
+                let len = file.metadata()?.len();
+                let zeros = [0; 4096];
+                let mut left = len;
+                while left > 0 {
+                    let chunk = left.min(zeros.len() as u64) as usize;
+                    file.write_all(&zeros[..chunk])?;
+                    left -= chunk as u64;
+                }
+                file.sync_all()?;
+            }
+        }
+    }
+    fs::remove_dir_all(path)
+}
+
+/// Wipes every alive workspace.
+///
+/// Called from signal handlers and the panic hook, where
+/// destructors do not run.
+pub fn wipe_all() {
+    let mut active = ACTIVE.lock().unwrap_or_else(PoisonError::into_inner);
+    for path in active.drain(..) {
+        if let Err(err) = wipe(&path) {
+            tracing::error!("Failed to wipe {}: {err}", path.display());
+        }
+    }
+}
+
+impl SecretWorkspace {
+    /// Creates workspace named after `prefix`, accessible
+    /// only by the current user.
+    pub fn new(prefix: &str) -> Result<Self> {
+        // Mode is given to `mkdir`, so the workspace is never
+        // accessible by others, not even for a moment.
+        let path = tempfile::Builder::new()
+            .prefix(prefix)
+            .permissions(Permissions::from_mode(0o700))
+            .tempdir_in(base())
+            .context("Failed to create secret workspace")?
+            .keep();
+
+        ACTIVE
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(path.clone());
+        Ok(Self { path })
+    }
+
+    /// Path of the workspace.
+    pub fn path(&self) -> &Path { &self.path }
+}
+
+impl Drop for SecretWorkspace {
+    fn drop(&mut self) {
+        let mut active = ACTIVE.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(index) = active.iter().position(|path| *path == self.path) else {
+            // Already wiped by a signal or the panic hook
+            return;
+        };
+        active.remove(index);
+        if let Err(err) = wipe(&self.path) {
+            tracing::error!("Failed to wipe {}: {err}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workspace_is_private_and_wiped() {
+        let workspace = SecretWorkspace::new("workspace").unwrap();
+        let path = workspace.path().to_path_buf();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        fs::write(path.join("key"), "secret").unwrap();
+        drop(workspace);
+        assert!(!path.exists());
+    }
+}
@@ -1,4 +1,15 @@
-{ lib, ... }:
 {
-  disks = import ./disks.nix { inherit lib; };
+  lib,
+  # Set by bootstrap, visible only with `--impure`
+  luksKeysDir ? builtins.getEnv "NIaC_LUKS_KEYS_DIR",
+  # Disks chosen by bootstrap instead of the declared ones, as JSON
+  diskDevices ? builtins.getEnv "NIaC_DISKS",
+  ...
+}:
+{
+  disks = import ./disks.nix (
+    { inherit lib; }
+    // lib.optionalAttrs (luksKeysDir != "") { inherit luksKeysDir; }
+    // lib.optionalAttrs (diskDevices != "") { devices = builtins.fromJSON diskDevices; }
+  );
 }
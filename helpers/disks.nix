@@ -2,6 +2,8 @@
 {
   lib,
   luksKeysDir ? "/tmp",
+  # Disk name to device in `/dev/disk/by-id`, replacing `device` of `mkDisk`
+  devices ? { },
   ...
 }:
 {
@@ -14,7 +16,7 @@
     {
       ${name} = {
         type = "disk";
-        device = "/dev/disk/by-id/" + device;
+        device = "/dev/disk/by-id/" + (devices.${name} or device);
         content = {
           type = "gpt";
           partitions = lib.mkMerge partitions;